@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+// Split a pretoken into its base symbols (one per char).
+pub(crate) fn split_chars(pretoken: &str) -> Vec<String> {
+    pretoken.chars().map(String::from).collect()
+}
+
+// GPT-2 byte-to-unicode table: printable bytes map to themselves, the rest
+// are shifted to code points from U+0100 so every byte is a visible char.
+pub(crate) fn byte_to_unicode() -> &'static [char; 256] {
+    static TABLE: OnceLock<[char; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = ['\0'; 256];
+        let mut shifted = 256;
+        for b in 0..=255u8 {
+            table[b as usize] = if matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+                b as char
+            } else {
+                shifted += 1;
+                char::from_u32(shifted - 1).unwrap()
+            };
+        }
+        table
+    })
+}
+
+// Inverse of `byte_to_unicode`, for decoding.
+pub(crate) fn unicode_to_byte() -> &'static HashMap<char, u8> {
+    static TABLE: OnceLock<HashMap<char, u8>> = OnceLock::new();
+    TABLE.get_or_init(|| byte_to_unicode().iter().enumerate().map(|(b, &c)| (c, b as u8)).collect())
+}
+
+// Map raw bytes to their byte-level symbols, one char per byte.
+pub(crate) fn bytes_to_symbols(bytes: &[u8]) -> String {
+    let table = byte_to_unicode();
+    bytes.iter().map(|&b| table[b as usize]).collect()
+}
+
+// Split a pretoken into byte-level base symbols (one per byte).
+pub(crate) fn split_bytes(pretoken: &[u8]) -> Vec<String> {
+    split_chars(&bytes_to_symbols(pretoken))
+}
@@ -0,0 +1,225 @@
+use std::{fs, io::{BufRead, BufReader}, path::Path};
+use arrow_array::{cast::AsArray, Array, RecordBatch};
+use arrow_schema::{DataType, Schema};
+use parquet::arrow::{arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder}, ProjectionMask};
+
+use crate::{
+    corpus::{Batch, TEXT_FIELDS},
+    CorpusStats, Result, TokenthingError,
+};
+
+// Rows decoded at a time from a Parquet row group
+const PARQUET_BATCH_SIZE: usize = 8192;
+
+fn is_string(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View)
+}
+
+// Index of the column holding the text: `text_field` if given, else `text`,
+// `content` or the first string column, as download_datasets.py picks fields.
+fn text_column(schema: &Schema, text_field: Option<&str>) -> std::result::Result<usize, String> {
+    let string_column = |name: &str| schema.index_of(name).ok().filter(|&i| is_string(schema.field(i).data_type()));
+    if let Some(field) = text_field {
+        return match schema.index_of(field) {
+            Ok(i) if is_string(schema.field(i).data_type()) => Ok(i),
+            Ok(i) => Err(format!("column {field:?} is {}, not a string", schema.field(i).data_type())),
+            Err(_) => Err(format!("no column {field:?}")),
+        };
+    }
+    TEXT_FIELDS
+        .iter()
+        .find_map(|&name| string_column(name))
+        .or_else(|| schema.fields().iter().position(|f| is_string(f.data_type())))
+        .ok_or_else(|| "no string column".to_string())
+}
+
+// The values of a string column, `None` for nulls.
+fn strings(column: &dyn Array) -> Box<dyn Iterator<Item = Option<&str>> + '_> {
+    match column.data_type() {
+        DataType::Utf8 => Box::new(column.as_string::<i32>().iter()),
+        DataType::LargeUtf8 => Box::new(column.as_string::<i64>().iter()),
+        _ => Box::new(column.as_string_view().iter()),
+    }
+}
+
+// Collects the text of record batches into document batches of about `size`
+// bytes, each document tagged with its 1-based row.
+struct DocumentBatcher<F: FnMut(Batch) -> bool> {
+    documents: Vec<(u64, Vec<u8>)>,
+    bytes: usize,
+    size: usize,
+    send: F,
+}
+
+impl<F: FnMut(Batch) -> bool> DocumentBatcher<F> {
+    // Add the rows of `column`, skipping nulls. Returns false once `send` does.
+    fn push(&mut self, column: &dyn Array, stats: &mut CorpusStats) -> bool {
+        let first_row = stats.lines + 1;
+        for (row, text) in (first_row..).zip(strings(column)) {
+            let Some(text) = text else { continue; };
+            self.bytes += text.len();
+            stats.bytes += text.len() as u64;
+            self.documents.push((row, text.as_bytes().to_vec()));
+        }
+        stats.lines += column.len() as u64;
+        self.bytes < self.size || self.flush()
+    }
+
+    fn flush(&mut self) -> bool {
+        self.bytes = 0;
+        self.documents.is_empty() || (self.send)(Batch::Documents(std::mem::take(&mut self.documents)))
+    }
+}
+
+fn corpus_error(path: &Path, message: impl ToString) -> TokenthingError {
+    TokenthingError::Corpus { path: path.to_path_buf(), line: None, message: message.to_string() }
+}
+
+// Stream the text column of an Arrow IPC file, in stream format as written by
+// the `datasets` library or in file format. `stats.lines` counts rows.
+pub(crate) fn read_arrow_batches(
+    path: &Path,
+    text_field: Option<&str>,
+    chunk_size: usize,
+    stats: &mut CorpusStats,
+    send: impl FnMut(Batch) -> bool,
+) -> Result<()> {
+    let open = || fs::File::open(path).map_err(|e| TokenthingError::io(path, e));
+    let mut reader = BufReader::new(open()?);
+    let is_file_format = reader.fill_buf().map_err(|e| TokenthingError::io(path, e))?.starts_with(b"ARROW1");
+    let batches: Box<dyn Iterator<Item = std::result::Result<RecordBatch, arrow_schema::ArrowError>>> = if is_file_format {
+        let reader = arrow_ipc::reader::FileReader::try_new(reader, None).map_err(|e| corpus_error(path, e))?;
+        let column = text_column(&reader.schema(), text_field).map_err(|e| corpus_error(path, e))?;
+        let reader = arrow_ipc::reader::FileReader::try_new(open()?, Some(vec![column])).map_err(|e| corpus_error(path, e))?;
+        Box::new(reader)
+    } else {
+        let reader = arrow_ipc::reader::StreamReader::try_new(reader, None).map_err(|e| corpus_error(path, e))?;
+        let column = text_column(&reader.schema(), text_field).map_err(|e| corpus_error(path, e))?;
+        let reader = arrow_ipc::reader::StreamReader::try_new_buffered(open()?, Some(vec![column]))
+            .map_err(|e| corpus_error(path, e))?;
+        Box::new(reader)
+    };
+
+    let mut batcher = DocumentBatcher { documents: Vec::new(), bytes: 0, size: chunk_size, send };
+    for batch in batches {
+        let batch = batch.map_err(|e| corpus_error(path, e))?;
+        if !batcher.push(batch.column(0), stats) { return Ok(()); }
+    }
+    batcher.flush();
+    Ok(())
+}
+
+// Reader of the text column of a Parquet file, one row group at a time and
+// decoding no other column.
+fn parquet_reader(path: &Path, text_field: Option<&str>) -> Result<ParquetRecordBatchReader> {
+    let file = fs::File::open(path).map_err(|e| TokenthingError::io(path, e))?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| corpus_error(path, e))?;
+    let column = text_column(builder.schema(), text_field).map_err(|e| corpus_error(path, e))?;
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [column]);
+    builder
+        .with_projection(mask)
+        .with_batch_size(PARQUET_BATCH_SIZE)
+        .build()
+        .map_err(|e| corpus_error(path, e))
+}
+
+// Stream the text column of a Parquet file. `stats.lines` counts rows.
+pub(crate) fn read_parquet_batches(
+    path: &Path,
+    text_field: Option<&str>,
+    chunk_size: usize,
+    stats: &mut CorpusStats,
+    send: impl FnMut(Batch) -> bool,
+) -> Result<()> {
+    let mut batcher = DocumentBatcher { documents: Vec::new(), bytes: 0, size: chunk_size, send };
+    for batch in parquet_reader(path, text_field)? {
+        let batch = batch.map_err(|e| corpus_error(path, e))?;
+        if !batcher.push(batch.column(0), stats) { return Ok(()); }
+    }
+    batcher.flush();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process, sync::Arc};
+    use crate::corpus::CHUNK_SIZE;
+    use arrow_array::{Int64Array, StringArray};
+    use arrow_schema::Field;
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+
+    // Rows 2, 4 and 7 are null
+    const TEXTS: [Option<&str>; 7] = [Some("one"), None, Some("three"), None, Some("five"), Some("six"), None];
+
+    fn documents(read: impl FnOnce(&mut CorpusStats, &mut dyn FnMut(Batch) -> bool) -> Result<()>) -> (Vec<(u64, String)>, CorpusStats) {
+        let mut stats = CorpusStats::default();
+        let mut documents = Vec::new();
+        read(&mut stats, &mut |batch| {
+            let Batch::Documents(batch) = batch else { panic!("expected documents") };
+            documents.extend(batch.into_iter().map(|(row, text)| (row, String::from_utf8(text).unwrap())));
+            true
+        })
+        .unwrap();
+        (documents, stats)
+    }
+
+    #[test]
+    fn parquet_rows_across_row_groups() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false), Field::new("text", DataType::Utf8, true)]));
+        let ids = Int64Array::from_iter_values(0..TEXTS.len() as i64);
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(ids), Arc::new(StringArray::from(TEXTS.to_vec()))]).unwrap();
+        let path = env::temp_dir().join(format!("tokenthing-rows-{}.parquet", process::id()));
+        let properties = WriterProperties::builder().set_max_row_group_size(3).build();
+        let mut writer = ArrowWriter::try_new(fs::File::create(&path).unwrap(), schema, Some(properties)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let row_groups = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).unwrap()).unwrap().metadata().num_row_groups();
+        // One-byte batches send every document on its own
+        let (documents, stats) = documents(|stats, send| read_parquet_batches(&path, None, 1, stats, send));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(row_groups, 3);
+        let expected = [(1, "one"), (3, "three"), (5, "five"), (6, "six")].map(|(row, text)| (row, text.to_string()));
+        assert_eq!(documents, expected);
+        assert_eq!((stats.lines, stats.bytes), (7, 15));
+    }
+
+    #[test]
+    fn arrow_stream_and_file_formats() {
+        // `content` wins over the first string column
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("title", DataType::Utf8, true),
+            Field::new("id", DataType::Int64, false),
+            Field::new("content", DataType::Utf8, true),
+        ]));
+        let titles = StringArray::from(vec!["t"; TEXTS.len()]);
+        let ids = Int64Array::from_iter_values(0..TEXTS.len() as i64);
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(titles), Arc::new(ids), Arc::new(StringArray::from(TEXTS.to_vec()))],
+        )
+        .unwrap();
+        let path = env::temp_dir().join(format!("tokenthing-rows-{}.arrow", process::id()));
+        let expected = [(1, "one"), (3, "three"), (5, "five"), (6, "six")].map(|(row, text)| (row, text.to_string()));
+
+        // Stream format, as the `datasets` library writes, in two record batches
+        let mut writer = arrow_ipc::writer::StreamWriter::try_new(fs::File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch.slice(0, 4)).unwrap();
+        writer.write(&batch.slice(4, 3)).unwrap();
+        writer.finish().unwrap();
+        let (stream, stats) = documents(|stats, send| read_arrow_batches(&path, None, CHUNK_SIZE, stats, send));
+        assert_eq!(stream, expected);
+        assert_eq!(stats.lines, 7);
+        let (titles, _) = documents(|stats, send| read_arrow_batches(&path, Some("title"), CHUNK_SIZE, stats, send));
+        assert_eq!(titles.len(), 7);
+
+        let mut writer = arrow_ipc::writer::FileWriter::try_new(fs::File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        let (file, stats) = documents(|stats, send| read_arrow_batches(&path, None, CHUNK_SIZE, stats, send));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(file, expected);
+        assert_eq!(stats.lines, 7);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{fs, path::{Path, PathBuf}};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{mix::check_shares, Corpus, InvalidUtf8, Normalizer, Pretokenizer, RecordSeparator, Result, SpecialTokens, TokenthingError};
+
+/// Config file used when neither `--config` nor `$TOKENTHING_CONFIG` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "cfg/config.yaml";
+/// Environment variable naming the config file.
+pub const CONFIG_ENV_VAR: &str = "TOKENTHING_CONFIG";
+
+/// Settings read from `cfg/config.yaml`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    /// Comma-separated Hugging Face datasets, read from the local cache when
+    /// neither `file_path` nor `corpora` is set
+    #[serde(default)]
+    pub hf_dataset_names: String,
+    /// Split of `hf_dataset_names` to train on; defaults to train
+    #[serde(default)]
+    pub hf_split: Option<String>,
+    /// Hugging Face cache root, instead of `$HF_HOME` or `~/.cache/huggingface`
+    #[serde(default)]
+    pub hf_cache_dir: Option<String>,
+    /// Corpus files: one path or a list of paths, glob patterns and directories
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub file_path: Vec<String>,
+    /// Weighted corpora to mix, instead of `file_path`
+    #[serde(default)]
+    pub corpora: Vec<Corpus>,
+    pub tokenizer_vocab_size: usize,
+    pub tokenizer_sequence_length: usize,
+    pub tokenizer_save_path: String,
+    /// Train and encode over raw bytes (GPT-2 style) instead of chars
+    #[serde(default)]
+    pub byte_level: bool,
+    /// Threads used to count the corpus; defaults to all available cores
+    #[serde(default)]
+    pub num_threads: Option<usize>,
+    /// Corpus documents that are not valid UTF-8: fail, skip, replace or bytes.
+    /// Defaults to bytes in byte-level mode, else fail.
+    #[serde(default)]
+    pub invalid_utf8: Option<InvalidUtf8>,
+    /// How the corpus is cut into documents: newline, blank_line, jsonl, csv,
+    /// arrow or parquet. Defaults to the one the file extension implies, else newline.
+    #[serde(default)]
+    pub record_separator: Option<String>,
+    /// Custom string separating documents, instead of a named separator
+    #[serde(default)]
+    pub record_delimiter: Option<String>,
+    /// JSONL field or CSV, Arrow or Parquet column holding the text; defaults
+    /// to text, then content, then the first string field
+    #[serde(default)]
+    pub text_field: Option<String>,
+    /// Keep line endings inside documents as text instead of dropping them
+    #[serde(default)]
+    pub keep_newlines: bool,
+    /// Reserved tokens: bos, eos, pad, unk and a list of `additional` ones
+    #[serde(default)]
+    pub special_tokens: SpecialTokens,
+    /// Normalization steps applied in order before pretokenizing, e.g.
+    /// `[nfkc, lowercase]`: nfc, nfkc, nfd, lowercase, strip_accents, strip_control
+    #[serde(default)]
+    pub normalizer: Normalizer,
+    /// Pretokenizer preset: default, gpt2, cl100k or llama3
+    #[serde(default)]
+    pub pretokenizer: Option<String>,
+    /// Custom pretokenizer regex, instead of a preset
+    #[serde(default)]
+    pub pretokenizer_pattern: Option<String>,
+}
+
+// Accept a single string as a one-element list.
+pub(crate) fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+impl Config {
+    /// The training corpora: `corpora`, or `file_path` as a single corpus,
+    /// else one corpus per dataset in `hf_dataset_names`.
+    pub fn corpora(&self) -> Result<Vec<Corpus>> {
+        let error = |message: &str| Err(TokenthingError::Config { path: None, message: message.to_string() });
+        let hf_datasets: Vec<&str> = self.hf_dataset_names.split(',').map(str::trim).filter(|n| !n.is_empty()).collect();
+        match (self.file_path.is_empty(), self.corpora.is_empty()) {
+            (false, false) => error("set either file_path or corpora, not both"),
+            (true, true) if hf_datasets.is_empty() => error("no training data: set file_path, corpora or hf_dataset_names"),
+            (true, true) => Ok(hf_datasets.into_iter().map(|name| Corpus::hf(name, self.hf_split.clone())).collect()),
+            (false, true) => Ok(vec![Corpus::new(self.file_path.clone())]),
+            (true, false) => check_shares(&self.corpora).map(|()| self.corpora.clone()),
+        }
+    }
+
+    /// The pretokenizer named by `pretokenizer` or `pretokenizer_pattern`
+    /// (at most one of them may be set), else the default one.
+    pub fn pretokenizer(&self) -> Result<Pretokenizer> {
+        match (&self.pretokenizer, &self.pretokenizer_pattern) {
+            (Some(_), Some(_)) => {
+                let message = "set either pretokenizer or pretokenizer_pattern, not both".to_string();
+                Err(TokenthingError::Config { path: None, message })
+            }
+            (Some(preset), None) => Pretokenizer::preset(preset),
+            (None, Some(pattern)) => Pretokenizer::new(pattern),
+            (None, None) => Ok(Pretokenizer::default()),
+        }
+    }
+
+    /// The separator named by `record_separator` or given by `record_delimiter`
+    /// (at most one of them may be set); `None` leaves it to the file extension.
+    pub fn record_separator(&self) -> Result<Option<RecordSeparator>> {
+        let error = |message: String| TokenthingError::Config { path: None, message };
+        match (&self.record_separator, &self.record_delimiter) {
+            (Some(_), Some(_)) => Err(error("set either record_separator or record_delimiter, not both".to_string())),
+            (Some(name), None) => name.parse().map(Some).map_err(error),
+            (None, Some(delimiter)) => Ok(Some(RecordSeparator::Delimiter(delimiter.clone()))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Config location: `flag` if given, else `$TOKENTHING_CONFIG`, else
+/// `cfg/config.yaml` relative to the working directory.
+pub fn config_path(flag: Option<&Path>) -> PathBuf {
+    match flag {
+        Some(path) => path.to_path_buf(),
+        None => std::env::var_os(CONFIG_ENV_VAR).map_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH), PathBuf::from),
+    }
+}
+
+/// Read and validate a config file. A bad pretokenizer pattern, record
+/// separator or corpus list fails here rather than at the start of training.
+pub fn load_config(config_path: &Path) -> Result<Config> {
+    let error = |message: String| TokenthingError::Config { path: Some(config_path.to_path_buf()), message };
+    let config_content = fs::read_to_string(config_path).map_err(|e| error(format!("cannot read file: {e}")))?;
+    let config: Config = serde_yaml::from_str(&config_content).map_err(|e| error(e.to_string()))?;
+    config.pretokenizer().and(config.record_separator().map(drop)).and(config.corpora().map(drop)).map_err(|e| match e {
+        TokenthingError::Config { message, .. } => error(message),
+        e => error(e.to_string()),
+    })?;
+    Ok(config)
+}
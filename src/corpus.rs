@@ -0,0 +1,604 @@
+use std::{borrow::Cow, fmt, fs, io::{BufRead, BufReader, Read}, path::{Path, PathBuf}, str::FromStr};
+
+use crate::{columnar, CorpusStats, Result, TokenthingError};
+
+/// How a corpus is cut into documents. Pretokens, and so merges, never cross a
+/// document boundary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RecordSeparator {
+    /// Every line is a document
+    #[default]
+    Newline,
+    /// Documents are separated by empty lines
+    BlankLine,
+    /// Documents are separated by a custom string, e.g. `<|doc|>`
+    Delimiter(String),
+    /// One JSON object per line; see [`text_field`](crate::TrainerBuilder::text_field)
+    Jsonl,
+    /// One CSV row per document, after a header row; see
+    /// [`text_field`](crate::TrainerBuilder::text_field)
+    Csv,
+    /// One Arrow IPC row per document, as in the `datasets` cache; see
+    /// [`text_field`](crate::TrainerBuilder::text_field)
+    Arrow,
+    /// One Parquet row per document; see [`text_field`](crate::TrainerBuilder::text_field)
+    Parquet,
+}
+
+impl FromStr for RecordSeparator {
+    type Err = String;
+
+    /// Parse a separator name; custom delimiters have no name.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(RecordSeparator::Newline),
+            "blank_line" => Ok(RecordSeparator::BlankLine),
+            "jsonl" => Ok(RecordSeparator::Jsonl),
+            "csv" => Ok(RecordSeparator::Csv),
+            "arrow" => Ok(RecordSeparator::Arrow),
+            "parquet" => Ok(RecordSeparator::Parquet),
+            _ => Err(format!(
+                "unknown record separator {s:?} (expected newline, blank_line, jsonl, csv, arrow or parquet)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RecordSeparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordSeparator::Newline => f.write_str("newline"),
+            RecordSeparator::BlankLine => f.write_str("blank_line"),
+            RecordSeparator::Delimiter(delimiter) => write!(f, "delimiter {delimiter:?}"),
+            RecordSeparator::Jsonl => f.write_str("jsonl"),
+            RecordSeparator::Csv => f.write_str("csv"),
+            RecordSeparator::Arrow => f.write_str("arrow"),
+            RecordSeparator::Parquet => f.write_str("parquet"),
+        }
+    }
+}
+
+impl RecordSeparator {
+    /// The separator implied by a file's extension: `.jsonl`/`.ndjson`, `.csv`,
+    /// `.arrow` and `.parquet` files are structured, anything else has one
+    /// document per line. A compression suffix is looked through, so
+    /// `.jsonl.gz` is JSONL.
+    pub fn for_path(path: &Path) -> Self {
+        let path = match (Compression::from_extension(path), path.file_stem()) {
+            (Some(_), Some(stem)) => Path::new(stem),
+            _ => path,
+        };
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "ndjson") => RecordSeparator::Jsonl,
+            Some("csv") => RecordSeparator::Csv,
+            Some("arrow") => RecordSeparator::Arrow,
+            Some("parquet") => RecordSeparator::Parquet,
+            _ => RecordSeparator::Newline,
+        }
+    }
+
+    // Whether a chunk that starts on a record boundary also ends on one.
+    fn ends_record(&self, chunk: &[u8]) -> bool {
+        match self {
+            // Structured files are never chunked: CSV fields may span lines
+            // and Arrow and Parquet are binary
+            RecordSeparator::Newline
+            | RecordSeparator::Jsonl
+            | RecordSeparator::Csv
+            | RecordSeparator::Arrow
+            | RecordSeparator::Parquet => chunk.ends_with(b"\n"),
+            RecordSeparator::BlankLine => chunk.ends_with(b"\n\n") || chunk.ends_with(b"\n\r\n"),
+            RecordSeparator::Delimiter(delimiter) => chunk.ends_with(delimiter.as_bytes()),
+        }
+    }
+
+    // Cut a chunk into records, each with the 0-based index of its first line
+    // within the chunk. Records keep their inner line endings; separators and
+    // empty records are dropped. A line-separated record's final line ending
+    // is part of the separator, so it is dropped too.
+    pub(crate) fn split<'a>(&self, chunk: &'a [u8]) -> Vec<(u64, &'a [u8])> {
+        let mut records = Vec::new();
+        match self {
+            RecordSeparator::Newline
+            | RecordSeparator::Jsonl
+            | RecordSeparator::Csv
+            | RecordSeparator::Arrow
+            | RecordSeparator::Parquet => {
+                for (i, line) in chunk.split_inclusive(|&b| b == b'\n').enumerate() {
+                    if !is_blank(line) { records.push((i as u64, strip_line_ending(line))); }
+                }
+            }
+            RecordSeparator::BlankLine => {
+                // (first line, byte offset) of the record being collected
+                let mut start: Option<(u64, usize)> = None;
+                let mut offset = 0;
+                for (i, line) in chunk.split_inclusive(|&b| b == b'\n').enumerate() {
+                    match (is_blank(line), start) {
+                        (true, Some((first, from))) => {
+                            records.push((first, strip_line_ending(&chunk[from..offset])));
+                            start = None;
+                        }
+                        (false, None) => start = Some((i as u64, offset)),
+                        _ => {}
+                    }
+                    offset += line.len();
+                }
+                if let Some((first, from)) = start { records.push((first, strip_line_ending(&chunk[from..]))); }
+            }
+            RecordSeparator::Delimiter(delimiter) => {
+                let delimiter = delimiter.as_bytes();
+                let (mut line, mut from, mut at) = (0, 0, 0);
+                while from < chunk.len() {
+                    let end = find(&chunk[at..], delimiter).map_or(chunk.len(), |i| at + i);
+                    if end > from { records.push((line, &chunk[from..end])); }
+                    let next = (end + delimiter.len()).min(chunk.len());
+                    line += chunk[from..next].iter().filter(|&&b| b == b'\n').count() as u64;
+                    (from, at) = (next, next);
+                }
+            }
+        }
+        records
+    }
+}
+
+// Whether a line holds nothing but its line ending.
+fn is_blank(line: &[u8]) -> bool {
+    matches!(line, b"" | b"\n" | b"\r\n")
+}
+
+fn strip_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Split a record into its lines, without line endings.
+pub(crate) fn lines(record: &[u8]) -> impl Iterator<Item = &[u8]> {
+    record.split(|&b| b == b'\n').map(strip_line_ending)
+}
+
+// Compression formats decoded transparently on input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    // Magic bytes win; the extension only decides for content without them,
+    // so a corrupt `.gz` file fails in the decoder instead of reading as text.
+    fn detect(path: &Path, head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if head.is_empty() {
+            None
+        } else {
+            Compression::from_extension(path)
+        }
+    }
+}
+
+/// Open a corpus file for streaming. Gzip, zstd and xz files (by magic bytes
+/// or `.gz`/`.zst`/`.xz` extension) are decompressed on the fly.
+pub fn open_input(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = fs::File::open(path).map_err(|e| TokenthingError::io(path, e))?;
+    let mut reader = BufReader::new(file);
+    let head = reader.fill_buf().map_err(|e| TokenthingError::io(path, e))?;
+    Ok(match Compression::detect(path, head) {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Some(Compression::Zstd) => {
+            let decoder = zstd::Decoder::with_buffer(reader).map_err(|e| TokenthingError::io(path, e))?;
+            Box::new(BufReader::new(decoder))
+        }
+        Some(Compression::Xz) => Box::new(BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))),
+    })
+}
+
+/// Expand corpus inputs into files, in order. Each input is a glob pattern
+/// (`data/*.jsonl`), a directory, searched recursively with hidden entries
+/// skipped, or a plain file. Matches of one input are sorted so the file
+/// order, and hence training, is reproducible.
+pub fn expand_inputs(inputs: &[impl AsRef<str>]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let error = |message: String| TokenthingError::Config { path: None, message: format!("file_path {input:?}: {message}") };
+        let mut matches = Vec::new();
+        if input.contains(['*', '?', '[']) {
+            for entry in glob::glob(input).map_err(|e| error(e.to_string()))? {
+                let path = entry.map_err(|e| TokenthingError::io(e.path(), std::io::Error::new(e.error().kind(), e.to_string())))?;
+                if path.is_dir() { walk_dir(&path, &mut matches)?; } else { matches.push(path); }
+            }
+            if matches.is_empty() { return Err(error("matches no files".to_string())); }
+        } else if Path::new(input).is_dir() {
+            walk_dir(Path::new(input), &mut matches)?;
+            if matches.is_empty() { return Err(error("directory holds no files".to_string())); }
+        } else {
+            // Missing files are reported when opened, with the I/O error
+            matches.push(PathBuf::from(input));
+        }
+        matches.sort();
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+// Collect the files under `dir`, skipping hidden files and directories.
+pub(crate) fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| TokenthingError::io(dir, e))? {
+        let path = entry.map_err(|e| TokenthingError::io(dir, e))?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) { continue; }
+        if path.is_dir() { walk_dir(&path, files)?; } else { files.push(path); }
+    }
+    Ok(())
+}
+
+// Default target size of the batches handed to counting threads
+pub(crate) const CHUNK_SIZE: usize = 4 << 20;
+
+// Work for a counting thread: a record-aligned chunk of raw corpus tagged with
+// its 1-based first line, or documents already pulled out of a structured
+// file, each tagged with its line.
+pub(crate) enum Batch {
+    Chunk { first_line: u64, bytes: Vec<u8> },
+    Documents(Vec<(u64, Vec<u8>)>),
+}
+
+// Read about `size` bytes, extended to the end of the record they stop in.
+// Returns an empty chunk at end of file.
+fn read_chunk(reader: &mut impl BufRead, size: usize, separator: &RecordSeparator) -> std::io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+    let last_byte = match separator {
+        RecordSeparator::Delimiter(delimiter) => *delimiter.as_bytes().last().unwrap_or(&b'\n'),
+        _ => b'\n',
+    };
+    while !chunk.is_empty() && !separator.ends_record(&chunk) {
+        if reader.read_until(last_byte, &mut chunk)? == 0 { break; }
+    }
+    Ok(chunk)
+}
+
+// Stream a corpus file as batches of about `chunk_size` bytes, counting lines
+// and bytes into `stats`. Stops early, without error, once `send` returns false.
+pub(crate) fn read_batches(
+    path: &Path,
+    separator: &RecordSeparator,
+    text_field: Option<&str>,
+    chunk_size: usize,
+    stats: &mut CorpusStats,
+    mut send: impl FnMut(Batch) -> bool,
+) -> Result<()> {
+    if matches!(separator, RecordSeparator::Arrow | RecordSeparator::Parquet) && Compression::from_extension(path).is_some() {
+        let message = format!("compressed {separator} files are not supported; decompress it first");
+        return Err(TokenthingError::Corpus { path: path.to_path_buf(), line: None, message });
+    }
+    match separator {
+        RecordSeparator::Arrow => return columnar::read_arrow_batches(path, text_field, chunk_size, stats, send),
+        RecordSeparator::Parquet => return columnar::read_parquet_batches(path, text_field, chunk_size, stats, send),
+        _ => {}
+    }
+    let mut reader = open_input(path)?;
+    if *separator == RecordSeparator::Csv {
+        return read_csv_batches(path, reader, text_field, chunk_size, stats, send);
+    }
+    // Delimited chunks may end mid-line; the line is counted once, at the end
+    let mut ends_line = true;
+    loop {
+        let chunk = read_chunk(&mut reader, chunk_size, separator)
+            .map_err(|e| TokenthingError::Io { path: path.to_path_buf(), line: Some(stats.lines + 1), source: e })?;
+        if chunk.is_empty() {
+            if !ends_line { stats.lines += 1; }
+            return Ok(());
+        }
+        let first_line = stats.lines + 1;
+        stats.lines += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+        ends_line = chunk.ends_with(b"\n");
+        stats.bytes += chunk.len() as u64;
+        if !send(Batch::Chunk { first_line, bytes: chunk }) { return Ok(()); }
+    }
+}
+
+/// Stream the documents of a corpus file with the reader training uses: cut
+/// by `separator`, with the text of JSONL, CSV, Arrow and Parquet records
+/// picked by `text_field` (see [`text_field`](crate::TrainerBuilder::text_field)).
+/// `f` gets each document and the 1-based line (or Arrow and Parquet row) it
+/// starts on, and returns false to stop early. Skipped records, such as null
+/// rows, leave gaps in the numbering. Returns what was read.
+pub fn read_documents(
+    path: &Path,
+    separator: &RecordSeparator,
+    text_field: Option<&str>,
+    mut f: impl FnMut(u64, &[u8]) -> bool,
+) -> Result<CorpusStats> {
+    let mut stats = CorpusStats::default();
+    let mut documents = 0;
+    let mut failure = None;
+    read_batches(path, separator, text_field, CHUNK_SIZE, &mut stats, |batch| {
+        let records: Vec<(u64, &[u8])> = match &batch {
+            Batch::Chunk { first_line, bytes } => separator.split(bytes).into_iter().map(|(i, r)| (first_line + i, r)).collect(),
+            Batch::Documents(records) => records.iter().map(|(line, record)| (*line, &record[..])).collect(),
+        };
+        for (line, record) in records {
+            let document = if *separator == RecordSeparator::Jsonl {
+                match jsonl_text(String::from_utf8_lossy(record).trim_end(), text_field) {
+                    Ok(Some(text)) => Cow::Owned(text.into_bytes()),
+                    Ok(None) => continue,
+                    Err(message) => {
+                        failure = Some(TokenthingError::Corpus { path: path.to_path_buf(), line: Some(line), message });
+                        return false;
+                    }
+                }
+            } else {
+                Cow::Borrowed(record)
+            };
+            documents += 1;
+            if !f(line, &document) { return false; }
+        }
+        true
+    })?;
+    stats.documents = documents;
+    failure.map_or(Ok(stats), Err)
+}
+
+// CSV rows are parsed here, on the reading thread, since quoted fields may
+// span lines and chunks could not be cut safely.
+fn read_csv_batches(
+    path: &Path,
+    reader: impl BufRead,
+    text_field: Option<&str>,
+    chunk_size: usize,
+    stats: &mut CorpusStats,
+    mut send: impl FnMut(Batch) -> bool,
+) -> Result<()> {
+    let csv_error = |e: csv::Error| {
+        let line = e.position().map(|p| p.line());
+        if !e.is_io_error() {
+            return TokenthingError::Corpus { path: path.to_path_buf(), line, message: e.to_string() };
+        }
+        match e.into_kind() {
+            csv::ErrorKind::Io(source) => TokenthingError::Io { path: path.to_path_buf(), line, source },
+            _ => unreachable!("checked to be an I/O error"),
+        }
+    };
+    let mut csv = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers: Vec<String> = csv.byte_headers().map_err(csv_error)?.iter().map(|h| String::from_utf8_lossy(h).into_owned()).collect();
+    let column = csv_text_column(&headers, text_field)
+        .map_err(|message| TokenthingError::Corpus { path: path.to_path_buf(), line: Some(1), message })?;
+
+    let mut record = csv::ByteRecord::new();
+    let (mut batch, mut batch_bytes) = (Vec::new(), 0);
+    let mut more = true;
+    while more {
+        more = csv.read_byte_record(&mut record).map_err(csv_error)?;
+        // Short rows have no text
+        if let (true, Some(text)) = (more, record.get(column)) {
+            batch_bytes += text.len();
+            batch.push((record.position().map_or(0, |p| p.line()), text.to_vec()));
+        }
+        if (!more || batch_bytes >= chunk_size) && !batch.is_empty() {
+            batch_bytes = 0;
+            if !send(Batch::Documents(std::mem::take(&mut batch))) { break; }
+        }
+    }
+    stats.lines += csv.position().line().saturating_sub(1);
+    stats.bytes += csv.position().byte();
+    Ok(())
+}
+
+// Fallback order when no text field is configured, as in download_datasets.py;
+// after these, the first string field is used.
+pub(crate) const TEXT_FIELDS: [&str; 2] = ["text", "content"];
+
+// Index of the CSV column holding the text. Every CSV field is a string, so
+// without `text` or `content` the first column is used.
+fn csv_text_column(headers: &[String], text_field: Option<&str>) -> std::result::Result<usize, String> {
+    let position = |name: &str| headers.iter().position(|h| h == name);
+    match text_field {
+        Some(field) => position(field).ok_or_else(|| format!("no column {field:?} in CSV header")),
+        None if headers.is_empty() => Err("CSV file has no columns".to_string()),
+        None => Ok(TEXT_FIELDS.iter().find_map(|&name| position(name)).unwrap_or(0)),
+    }
+}
+
+// The text of one JSONL record: `text_field` if given, else the first of
+// `text`, `content` or any string field. `None` when the record has no string
+// field at all, which is skipped like download_datasets.py does.
+pub(crate) fn jsonl_text(line: &str, text_field: Option<&str>) -> std::result::Result<Option<String>, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| format!("invalid JSON: {e}"))?;
+    let serde_json::Value::Object(mut object) = value else {
+        return Err("JSONL record is not an object".to_string());
+    };
+    let string = |value: serde_json::Value| match value {
+        serde_json::Value::String(text) => Some(text),
+        _ => None,
+    };
+    if let Some(field) = text_field {
+        return match object.swap_remove(field) {
+            Some(serde_json::Value::String(text)) => Ok(Some(text)),
+            Some(_) => Err(format!("field {field:?} is not a string")),
+            None => Err(format!("no {field:?} field")),
+        };
+    }
+    if let Some(text) = TEXT_FIELDS.iter().find_map(|&name| object.get(name).filter(|v| v.is_string())) {
+        return Ok(text.as_str().map(str::to_string));
+    }
+    Ok(object.into_iter().find_map(|(_, value)| string(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, io::Cursor, process};
+
+    fn records(separator: &RecordSeparator, chunk: &str) -> Vec<(u64, String)> {
+        separator.split(chunk.as_bytes()).into_iter().map(|(line, r)| (line, String::from_utf8_lossy(r).into_owned())).collect()
+    }
+
+    fn chunks(separator: &RecordSeparator, data: &str, size: usize) -> Vec<String> {
+        let mut reader = Cursor::new(data.as_bytes());
+        let mut chunks = Vec::new();
+        loop {
+            let chunk = read_chunk(&mut reader, size, separator).unwrap();
+            if chunk.is_empty() { return chunks; }
+            chunks.push(String::from_utf8(chunk).unwrap());
+        }
+    }
+
+    #[test]
+    fn newline_records_drop_line_endings() {
+        let expected = [(0, "a".to_string()), (2, "b".to_string())];
+        assert_eq!(records(&RecordSeparator::Newline, "a\r\n\nb"), expected);
+    }
+
+    #[test]
+    fn blank_line_records_with_crlf() {
+        let expected = [(0, "a\r\nb".to_string()), (4, "c".to_string())];
+        assert_eq!(records(&RecordSeparator::BlankLine, "a\r\nb\r\n\r\n\r\nc\r\n"), expected);
+        assert!(RecordSeparator::BlankLine.ends_record(b"a\r\n\r\n"));
+        assert!(!RecordSeparator::BlankLine.ends_record(b"a\r\n"));
+    }
+
+    #[test]
+    fn delimiter_records_and_lines() {
+        let separator = RecordSeparator::Delimiter("<|doc|>".to_string());
+        let expected = [(0, "one".to_string()), (0, "two\nlines".to_string()), (1, "\nthree".to_string())];
+        assert_eq!(records(&separator, "one<|doc|>two\nlines<|doc|><|doc|>\nthree"), expected);
+    }
+
+    #[test]
+    fn chunks_extend_over_a_straddling_delimiter() {
+        let separator = RecordSeparator::Delimiter("<|doc|>".to_string());
+        assert_eq!(chunks(&separator, "aaaa<|doc|>b>b<|doc|>cc", 6), ["aaaa<|doc|>", "b>b<|doc|>", "cc"]);
+        // The delimiter's last byte alone does not end a chunk
+        let separator = RecordSeparator::Delimiter("ab".to_string());
+        assert_eq!(chunks(&separator, "xbyyabz", 1), ["xbyyab", "z"]);
+        assert_eq!(chunks(&RecordSeparator::BlankLine, "a\nb\n\nc\n", 1), ["a\nb\n\n", "c\n"]);
+    }
+
+    #[test]
+    fn delimited_chunks_count_lines_once() {
+        let path = env::temp_dir().join(format!("tokenthing-delimited-{}.txt", process::id()));
+        fs::write(&path, "a\nb<|doc|>c\nd<|doc|>e\n").unwrap();
+        let separator = RecordSeparator::Delimiter("<|doc|>".to_string());
+        let mut stats = CorpusStats::default();
+        let mut first_lines = Vec::new();
+        read_batches(&path, &separator, None, 1, &mut stats, |batch| {
+            if let Batch::Chunk { first_line, .. } = batch { first_lines.push(first_line); }
+            true
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(first_lines, [1, 2, 3]);
+        assert_eq!(stats.lines, 3);
+    }
+
+    #[test]
+    fn jsonl_text_field_fallback() {
+        let text = |line: &str, field: Option<&str>| jsonl_text(line, field);
+        let record = r#"{"id": 1, "title": "t", "content": "c", "text": "x"}"#;
+        assert_eq!(text(record, None), Ok(Some("x".to_string())));
+        assert_eq!(text(r#"{"id": 1, "title": "t", "content": "c"}"#, None), Ok(Some("c".to_string())));
+        assert_eq!(text(r#"{"id": 1, "title": "t", "body": "b"}"#, None), Ok(Some("t".to_string())));
+        // A non-string `text` falls through to the next candidate
+        assert_eq!(text(r#"{"text": 5, "body": "b"}"#, None), Ok(Some("b".to_string())));
+        assert_eq!(text(r#"{"id": 1}"#, None), Ok(None));
+        assert_eq!(text(record, Some("title")), Ok(Some("t".to_string())));
+        assert!(text(record, Some("id")).unwrap_err().contains("not a string"));
+        assert!(text(record, Some("body")).unwrap_err().contains("no \"body\" field"));
+        assert!(text("[1]", None).is_err());
+        assert!(text("{", None).unwrap_err().starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn csv_skips_short_rows() {
+        let data = "id,text\n1,hello\n2\n3,\"multi\nline\"\n";
+        let mut stats = CorpusStats::default();
+        let mut documents = Vec::new();
+        read_csv_batches(Path::new("test.csv"), Cursor::new(data), None, CHUNK_SIZE, &mut stats, |batch| {
+            if let Batch::Documents(batch) = batch { documents.extend(batch); }
+            true
+        })
+        .unwrap();
+        assert_eq!(documents, [(2, b"hello".to_vec()), (4, b"multi\nline".to_vec())]);
+        assert_eq!(stats.lines, 5);
+        assert_eq!(csv_text_column(&["id".to_string(), "body".to_string()], None), Ok(0));
+        assert_eq!(csv_text_column(&["id".to_string(), "content".to_string()], None), Ok(1));
+        assert!(csv_text_column(&["id".to_string()], Some("text")).is_err());
+    }
+
+    #[test]
+    fn compressed_inputs_are_decoded() {
+        use std::io::Write;
+        let text = b"first line\nsecond line\n";
+        let gzip = {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(text).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstd = zstd::encode_all(&text[..], 0).unwrap();
+        let xz = {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(text).unwrap();
+            encoder.finish().unwrap()
+        };
+        let dir = env::temp_dir().join(format!("tokenthing-compression-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cases = [
+            ("corpus.txt.gz", &gzip[..]),
+            ("corpus.txt.zst", &zstd[..]),
+            ("corpus.txt.xz", &xz[..]),
+            // Magic bytes are enough without an extension
+            ("corpus", &zstd[..]),
+            ("corpus.txt", &text[..]),
+        ];
+        for (name, bytes) in cases {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+            let mut read = Vec::new();
+            open_input(&path).unwrap().read_to_end(&mut read).unwrap();
+            assert_eq!(read, text, "{name}");
+        }
+        // Without magic bytes the extension decides, so plain text named
+        // `.gz` fails in the decoder rather than being read as text
+        let path = dir.join("plain.txt.gz");
+        fs::write(&path, text).unwrap();
+        let err = open_input(&path).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("gzip header"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn documents_from_jsonl_and_compressed_columnar() {
+        let path = env::temp_dir().join(format!("tokenthing-documents-{}.jsonl", process::id()));
+        fs::write(&path, "{\"text\": \"a\"}\n\n{\"id\": 1}\n{\"text\": \"b\"}\n").unwrap();
+        let mut documents = Vec::new();
+        let stats = read_documents(&path, &RecordSeparator::Jsonl, None, |line, text| {
+            documents.push((line, text.to_vec()));
+            true
+        });
+        fs::remove_file(&path).unwrap();
+        assert_eq!(documents, [(1, b"a".to_vec()), (4, b"b".to_vec())]);
+        assert_eq!(stats.unwrap().documents, 2);
+        let err = read_documents(Path::new("x.parquet.gz"), &RecordSeparator::Parquet, None, |_, _| true).unwrap_err();
+        assert!(err.to_string().contains("compressed parquet files are not supported"));
+    }
+}
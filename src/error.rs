@@ -0,0 +1,73 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+
+/// Everything that can go wrong in tokenthing. File-backed variants carry the
+/// path, and corpus errors the 1-based line, so failures deep into a large
+/// file point at the offending spot.
+#[derive(Debug)]
+pub enum TokenthingError {
+    /// Config file missing, unreadable or invalid, or inconsistent settings
+    /// (`path` is `None` when they did not come from a file).
+    Config { path: Option<PathBuf>, message: String },
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, line: Option<u64>, source: io::Error },
+    /// Invalid UTF-8: in an input file (with path and line), or in decoded
+    /// output. `offset` is the byte offset of the first bad byte in the line
+    /// or output.
+    Utf8 { path: Option<PathBuf>, line: Option<u64>, offset: usize },
+    /// A corpus record could not be parsed, e.g. a JSONL line without text.
+    Corpus { path: PathBuf, line: Option<u64>, message: String },
+    /// Invalid pretokenizer pattern, or one that hit the backtracking limit
+    /// on some input.
+    Regex(fancy_regex::Error),
+    /// Saved model unreadable, inconsistent or of an unsupported version.
+    ModelFormat { path: PathBuf, message: String },
+    /// Decoding met an ID outside the vocabulary.
+    UnknownTokenId(u32),
+}
+
+pub type Result<T> = std::result::Result<T, TokenthingError>;
+
+impl TokenthingError {
+    pub(crate) fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        TokenthingError::Io { path: path.as_ref().to_path_buf(), line: None, source }
+    }
+}
+
+impl fmt::Display for TokenthingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenthingError::Config { path: Some(path), message } => write!(f, "config {}: {message}", path.display()),
+            TokenthingError::Config { path: None, message } => write!(f, "config: {message}"),
+            TokenthingError::Io { path, line: Some(line), source } => write!(f, "{}:{line}: {source}", path.display()),
+            TokenthingError::Io { path, line: None, source } => write!(f, "{}: {source}", path.display()),
+            TokenthingError::Utf8 { path, line, offset } => {
+                if let Some(path) = path { write!(f, "{}:", path.display())?; }
+                if let Some(line) = line { write!(f, "{line}:")?; }
+                if path.is_some() || line.is_some() { write!(f, " ")?; }
+                write!(f, "invalid UTF-8 at byte {offset}")
+            }
+            TokenthingError::Corpus { path, line: Some(line), message } => write!(f, "{}:{line}: {message}", path.display()),
+            TokenthingError::Corpus { path, line: None, message } => write!(f, "{}: {message}", path.display()),
+            TokenthingError::Regex(e @ fancy_regex::Error::RuntimeError(_)) => write!(f, "pretokenizer failed: {e}"),
+            TokenthingError::Regex(e) => write!(f, "invalid pretokenizer pattern: {e}"),
+            TokenthingError::ModelFormat { path, message } => write!(f, "model {}: {message}", path.display()),
+            TokenthingError::UnknownTokenId(id) => write!(f, "unknown token ID {id}"),
+        }
+    }
+}
+
+impl std::error::Error for TokenthingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TokenthingError::Io { source, .. } => Some(source),
+            TokenthingError::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<fancy_regex::Error> for TokenthingError {
+    fn from(e: fancy_regex::Error) -> Self {
+        TokenthingError::Regex(e)
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}, time::SystemTime};
+
+use crate::{corpus::walk_dir, Result, TokenthingError};
+
+// The `datasets` and hub cache directories. `cache_dir` plays the part of
+// $HF_HOME; without it the usual environment variables apply.
+fn cache_dirs(cache_dir: Option<&Path>) -> (PathBuf, PathBuf) {
+    if let Some(root) = cache_dir {
+        return (root.join("datasets"), root.join("hub"));
+    }
+    let root = env::var_os("HF_HOME").map(PathBuf::from).unwrap_or_else(|| {
+        let home = env::var_os("HOME").map_or_else(|| PathBuf::from("."), PathBuf::from);
+        home.join(".cache").join("huggingface")
+    });
+    let datasets = env::var_os("HF_DATASETS_CACHE").map_or_else(|| root.join("datasets"), PathBuf::from);
+    let hub = env::var_os("HF_HUB_CACHE").map_or_else(|| root.join("hub"), PathBuf::from);
+    (datasets, hub)
+}
+
+// Dataset names compare without case and punctuation, since the `datasets`
+// cache turns "roneneldan/TinyStories" into a directory like "roneneldan___tiny_stories".
+fn name_key(name: &str) -> String {
+    name.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+// Whether a shard file belongs to `split`: "train-00000-of-00004.parquet",
+// "tiny_stories-train.arrow" or anything under a "train" directory.
+fn in_split(relative: &Path, split: &str) -> bool {
+    let Some(stem) = relative.file_stem().and_then(|s| s.to_str()) else { return false; };
+    let in_dir = relative.parent().is_some_and(|dir| dir.components().any(|c| c.as_os_str() == split));
+    in_dir
+        || stem == split
+        || stem.starts_with(&format!("{split}-"))
+        || stem.ends_with(&format!("-{split}"))
+        || stem.contains(&format!("-{split}-"))
+}
+
+fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+// Shards of `extension` under `dir` that belong to `split`, sorted.
+fn split_files(dir: &Path, extension: &str, split: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    walk_dir(dir, &mut files)?;
+    files.retain(|f| {
+        f.extension().is_some_and(|e| e == extension) && in_split(f.strip_prefix(dir).unwrap_or(f), split)
+    });
+    files.sort();
+    Ok(files)
+}
+
+// Arrow shards written by `datasets.load_dataset`, under
+// <name>/<config>/<version>/<hash>/. With several builds, the most recently
+// written one is used.
+fn datasets_cache_files(datasets_dir: &Path, name: &str, split: &str) -> Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(datasets_dir) else { return Ok(Vec::new()); };
+    let key = name_key(name);
+    // Shards grouped by the build directory holding them
+    let mut builds: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for entry in entries.flatten() {
+        if !entry.path().is_dir() || name_key(&entry.file_name().to_string_lossy()) != key { continue; }
+        for file in split_files(&entry.path(), "arrow", split)? {
+            builds.entry(file.parent().map(Path::to_path_buf).unwrap_or_default()).or_default().push(file);
+        }
+    }
+    Ok(builds.into_iter().max_by_key(|(build, _)| modified(build)).map(|(_, files)| files).unwrap_or_default())
+}
+
+// Parquet shards in the hub cache, under datasets--<org>--<name>/snapshots/<revision>/.
+// The revision `refs/main` points at is used, else the newest snapshot.
+fn hub_cache_files(hub_dir: &Path, name: &str, split: &str) -> Result<Vec<PathBuf>> {
+    let repo = hub_dir.join(format!("datasets--{}", name.replace('/', "--")));
+    let snapshots = repo.join("snapshots");
+    let main = fs::read_to_string(repo.join("refs").join("main")).ok().map(|r| snapshots.join(r.trim()));
+    let snapshot = match main.filter(|s| s.is_dir()) {
+        Some(snapshot) => snapshot,
+        None => {
+            let Ok(entries) = fs::read_dir(&snapshots) else { return Ok(Vec::new()); };
+            let newest = entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).max_by_key(|p| modified(p));
+            let Some(snapshot) = newest else { return Ok(Vec::new()); };
+            snapshot
+        }
+    };
+    split_files(&snapshot, "parquet", split)
+}
+
+/// Shard files of a Hugging Face dataset split that is already in the local
+/// cache, without any network access: Arrow files from the `datasets` cache,
+/// else Parquet files from the hub cache. `cache_dir` overrides `$HF_HOME`
+/// (default `~/.cache/huggingface`); `$HF_DATASETS_CACHE` and `$HF_HUB_CACHE`
+/// are honoured too.
+pub fn hf_dataset_files(name: &str, split: &str, cache_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let (datasets_dir, hub_dir) = cache_dirs(cache_dir);
+    let mut files = datasets_cache_files(&datasets_dir, name, split)?;
+    if files.is_empty() {
+        files = hub_cache_files(&hub_dir, name, split)?;
+    }
+    if files.is_empty() {
+        let message = format!(
+            "dataset {name:?} split {split:?} is not in the Hugging Face cache ({} or {})",
+            datasets_dir.display(),
+            hub_dir.display()
+        );
+        return Err(TokenthingError::Config { path: None, message });
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn names_match_cache_directories() {
+        assert_eq!(name_key("roneneldan/TinyStories"), name_key("roneneldan___tiny_stories"));
+        assert_eq!(name_key("HuggingFaceFW/fineweb-edu"), name_key("huggingfacefw___fineweb_edu"));
+        assert_ne!(name_key("roneneldan/TinyStories"), name_key("roneneldan___tiny_stories_v2"));
+    }
+
+    #[test]
+    fn shard_names_select_their_split() {
+        for shard in [
+            "tiny_stories-train.arrow",
+            "tiny_stories-train-00000-of-00004.arrow",
+            "data/train-00000-of-00001.parquet",
+            "train/data-00000-of-00002.arrow",
+            "train.parquet",
+        ] {
+            assert!(in_split(Path::new(shard), "train"), "{shard}");
+        }
+        for shard in ["tiny_stories-validation.arrow", "data/training-00000.parquet", "cache-1a2b3c.arrow", "trainer.arrow"] {
+            assert!(!in_split(Path::new(shard), "train"), "{shard}");
+        }
+    }
+
+    #[test]
+    fn finds_cached_arrow_shards() {
+        let root = env::temp_dir().join(format!("tokenthing-hf-{}", process::id()));
+        let build = root.join("datasets/roneneldan___tiny_stories/default/0.0.0/0123abcd");
+        fs::create_dir_all(&build).unwrap();
+        for shard in ["tiny_stories-train-00001-of-00002.arrow", "tiny_stories-train-00000-of-00002.arrow", "tiny_stories-validation.arrow"] {
+            fs::write(build.join(shard), b"").unwrap();
+        }
+        let files = hf_dataset_files("roneneldan/TinyStories", "train", Some(&root)).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["tiny_stories-train-00000-of-00002.arrow", "tiny_stories-train-00001-of-00002.arrow"]);
+        assert!(hf_dataset_files("roneneldan/TinyStories", "test", Some(&root)).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! Byte-pair-encoding tokenizer: training, encoding and decoding.
+//!
+//! ```no_run
+//! use tokenthing::{Tokenizer, Trainer};
+//!
+//! let trainer = Trainer::builder().vocab_size(1000).byte_level(true).build()?;
+//! let model = trainer.train_file("corpus.txt")?;
+//! model.save("tokenizer_model")?;
+//!
+//! let tokenizer = Tokenizer::from_model(model)?;
+//! let ids = tokenizer.encode("hello world")?;
+//! assert_eq!(tokenizer.decode(&ids, false)?, "hello world");
+//! # Ok::<(), tokenthing::TokenthingError>(())
+//! ```
+
+mod byte_level;
+mod columnar;
+mod config;
+mod corpus;
+mod error;
+mod hf;
+mod mix;
+mod model;
+mod normalize;
+mod pretokenize;
+mod special;
+mod tokenizer;
+mod trainer;
+mod vocab;
+mod words;
+
+pub use config::{config_path, load_config, Config, CONFIG_ENV_VAR, DEFAULT_CONFIG_PATH};
+pub use corpus::{expand_inputs, open_input, read_documents, RecordSeparator};
+pub use error::{Result, TokenthingError};
+pub use hf::hf_dataset_files;
+pub use mix::{Corpus, CorpusReport};
+pub use model::{TokenizerModel, TrainingMetadata, MODEL_FILE_NAME, MODEL_VERSION};
+pub use normalize::{Normalizer, NormalizerStep};
+pub use pretokenize::Pretokenizer;
+pub use special::SpecialTokens;
+pub use tokenizer::Tokenizer;
+pub use trainer::{Trainer, TrainerBuilder};
+pub use vocab::Vocab;
+pub use words::{CorpusStats, InvalidUtf8, WordCounts};
+
+/// A merge rule: the left and right token of an adjacent pair.
+pub type TokenPair = (String, String);
@@ -1,140 +1,413 @@
-use std::{collections::{HashMap}, fs, io::{BufRead, BufReader}};
-use serde::{Deserialize, Serialize};
-use regex::Regex;
+use std::{io::{BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, process::ExitCode};
+use clap::{Args, Parser, Subcommand};
+use tokenthing::{
+    config_path, load_config, open_input, read_documents, Config, Corpus, CorpusStats, InvalidUtf8, Normalizer,
+    NormalizerStep, RecordSeparator, Tokenizer, TokenizerModel, TokenthingError, Trainer,
+};
 
-type TokenPair = (String,String);
-type PairFreqs = HashMap<TokenPair, u32>;
 type ResultE = Result<(), Box<dyn std::error::Error>>;
 
-#[derive(Debug, Deserialize, Serialize)]
-struct Config {
-    hf_dataset_names: String,
-    file_path: String,
-    tokenizer_vocab_size: usize,
-    tokenizer_sequence_length: usize,
-}
+fn train_tokenizer(config: &Config) -> ResultE {
+    let trainer = Trainer::from_config(config)?;
+    let corpora = config.corpora()?;
+    let (word_counts, reports) = trainer.count_corpora(&corpora)?;
+    let mut out = std::io::stdout().lock();
+    let mut stats = CorpusStats::default();
+    let mut num_files = 0;
+    for (corpus, report) in corpora.iter().zip(&reports) {
+        if corpora.len() > 1 {
+            writeln!(out, "Corpus {corpus}: {} pretokens, scaled by {:.3}", report.pretokens, report.scale)?;
+        }
+        for (path, file) in &report.files {
+            if report.files.len() > 1 || corpora.len() > 1 {
+                writeln!(out, "  {}: {} lines, {} documents ({} bytes)", path.display(), file.lines, file.documents, file.bytes)?;
+            }
+            stats += *file;
+        }
+        num_files += report.files.len();
+    }
+    let plural = if num_files == 1 { "" } else { "s" };
+    writeln!(out, "Read {} lines, {} documents ({} bytes) from {num_files} file{plural}", stats.lines, stats.documents, stats.bytes)?;
+    writeln!(out, "Counted {} distinct words ({} pretokens)", word_counts.len(), word_counts.total())?;
 
-fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
-    let config_path = "/home/j/Projects/Tokenthing/cfg/config.yaml";
-    let config_content = fs::read_to_string(config_path)?;
-    let config = serde_yaml::from_str(&config_content)?;
-    Ok(config)
-}
+    let mut model = trainer.train(&word_counts);
+    model.metadata.corpus = corpora.iter().map(Corpus::to_string).collect::<Vec<_>>().join("; ");
+    writeln!(out, "Initial alphabet: {} symbols", model.metadata.alphabet_size)?;
+    writeln!(out, "Learned {} merges", model.merges.len())?;
+    writeln!(out, "Fingerprint: {}", model.metadata.fingerprint)?;
+    writeln!(out, "Vocabulary: {} tokens", model.vocab.len())?;
 
-// Apply learned merges to a token sequence.
-// Performs greedy left-to-right passes using the learned pair ranks.
-fn apply_merges_to_tokens(mut tokens: Vec<String>, merges: &[(String, String)]) -> Vec<String> {
-    if merges.is_empty() || tokens.len() < 2 {
-        return tokens;
-    }
+    let path = model.save(&config.tokenizer_save_path)?;
+    writeln!(out, "Saved tokenizer to {}", path.display())?;
 
-    let mut ranks: HashMap<(String, String), usize> = HashMap::new();
-    for (i, (a, b)) in merges.iter().enumerate() {
-        ranks.insert((a.clone(), b.clone()), i);
+    // In char mode the alphabet is only known after counting, so it can overshoot the target
+    if model.vocab.len() > model.metadata.vocab_size {
+        eprintln!(
+            "warning: vocabulary has {} tokens, more than the target {}: the corpus alphabet ({} symbols) and special tokens do not fit",
+            model.vocab.len(),
+            model.metadata.vocab_size,
+            model.metadata.alphabet_size
+        );
     }
-
-    loop {
-        if tokens.len() < 2 { break; }
-        let mut i = 0;
-        let mut merged_any = false;
-        while i + 1 < tokens.len() {
-            let pair = (tokens[i].clone(), tokens[i + 1].clone());
-            if ranks.contains_key(&pair) {
-                let new_tok = format!("{}{}", pair.0, pair.1);
-                tokens[i] = new_tok;
-                tokens.remove(i + 1);
-                merged_any = true;
-            } else {
-                i += 1;
-            }
-        }
-        if !merged_any { break; }
+    if stats.invalid_utf8_documents > 0 {
+        let action = match trainer.invalid_utf8() {
+            InvalidUtf8::Skip => "skipped",
+            InvalidUtf8::Replace => "replaced lossily",
+            InvalidUtf8::Bytes => "kept as raw bytes",
+            InvalidUtf8::Fail => unreachable!("fail stops at the first invalid line"),
+        };
+        eprintln!("warning: {} documents with invalid UTF-8 {action}", stats.invalid_utf8_documents);
     }
+    Ok(())
+}
 
-    tokens
+#[derive(Parser)]
+#[command(name = "tokenthing", about = "Train and run BPE tokenizers")]
+struct Cli {
+    /// Config file [default: $TOKENTHING_CONFIG, else cfg/config.yaml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn count_token_pairs(tokens: &[String]) -> PairFreqs {
-    let mut pair_freqs = PairFreqs::new();
-    
-    for window in tokens.windows(2) {
-        let pair = (window[0].clone(), window[1].clone());
-        *pair_freqs.entry(pair).or_insert(0) += 1;
-    }
-    pair_freqs
+#[derive(Subcommand)]
+enum Command {
+    /// Train a tokenizer and save it to tokenizer_save_path
+    Train(Box<TrainArgs>),
+    /// Encode text to token IDs, one output line per input line or Parquet row
+    Encode {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Text file to encode, optionally gzip/zstd/xz compressed, or Parquet file [default: stdin]
+        input: Option<PathBuf>,
+        /// Parquet column holding the text [default: text, then content, then the first string column]
+        #[arg(long)]
+        text_field: Option<String>,
+    },
+    /// Decode whitespace-separated token IDs, one output line per input line
+    Decode {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// File of token IDs [default: stdin]
+        input: Option<PathBuf>,
+        /// Leave special tokens out of the output
+        #[arg(long)]
+        skip_special_tokens: bool,
+        /// Replace unknown IDs and invalid UTF-8 with U+FFFD instead of failing
+        #[arg(long)]
+        lossy: bool,
+    },
+    /// Print vocabulary and merge information for a saved model
+    Inspect {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Number of merges and tokens to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
 }
 
-// Map step: tokenize a text slice, apply current merges, then count pairs
-fn map_count_pairs(text: &str, re: &Regex, merges: &[(String, String)]) -> PairFreqs {
-    let base_tokens: Vec<String> = pretokenize(re, text).map(str::to_string).collect();
-    let tokens = apply_merges_to_tokens(base_tokens, merges);
-    count_token_pairs(&tokens)
+// Flags that override individual config fields for a training run.
+#[derive(Args)]
+struct TrainArgs {
+    /// Corpus file, glob or directory; repeat for several [config: file_path]
+    #[arg(long)]
+    file_path: Vec<String>,
+    /// Hugging Face dataset in the local cache; repeat for several [config: hf_dataset_names]
+    #[arg(long)]
+    hf_dataset: Vec<String>,
+    /// Split of the Hugging Face datasets [config: hf_split]
+    #[arg(long)]
+    hf_split: Option<String>,
+    /// Hugging Face cache root [config: hf_cache_dir]
+    #[arg(long)]
+    hf_cache_dir: Option<String>,
+    /// Target vocabulary size [config: tokenizer_vocab_size]
+    #[arg(long)]
+    vocab_size: Option<usize>,
+    /// Output directory [config: tokenizer_save_path]
+    #[arg(long)]
+    save_path: Option<String>,
+    /// Train over raw bytes [config: byte_level]
+    #[arg(long)]
+    byte_level: Option<bool>,
+    /// Counting threads [config: num_threads]
+    #[arg(long)]
+    num_threads: Option<usize>,
+    /// Document separator: newline, blank_line, jsonl, csv, arrow or parquet [config: record_separator]
+    #[arg(long, conflicts_with = "record_delimiter")]
+    record_separator: Option<String>,
+    /// Custom document separator string [config: record_delimiter]
+    #[arg(long)]
+    record_delimiter: Option<String>,
+    /// JSONL field or CSV, Arrow or Parquet column holding the text [config: text_field]
+    #[arg(long)]
+    text_field: Option<String>,
+    /// Keep line endings inside documents as text [config: keep_newlines]
+    #[arg(long)]
+    keep_newlines: Option<bool>,
+    /// Invalid UTF-8 documents: fail, skip, replace or bytes [config: invalid_utf8]
+    #[arg(long)]
+    invalid_utf8: Option<InvalidUtf8>,
+    /// Comma-separated normalizer steps, e.g. nfkc,lowercase; empty for none [config: normalizer]
+    #[arg(long, value_delimiter = ',')]
+    normalizer: Option<Vec<NormalizerStep>>,
+    /// Pretokenizer preset: default, gpt2, cl100k or llama3 [config: pretokenizer]
+    #[arg(long, conflicts_with = "pretokenizer_pattern")]
+    pretokenizer: Option<String>,
+    /// Custom pretokenizer regex [config: pretokenizer_pattern]
+    #[arg(long)]
+    pretokenizer_pattern: Option<String>,
 }
 
+impl TrainArgs {
+    fn apply(self, config: &mut Config) {
+        // A file flag trains on those files alone, replacing any corpus mix
+        if !self.file_path.is_empty() {
+            config.file_path = self.file_path;
+            config.corpora.clear();
+        }
+        // Likewise for datasets, which only apply without files or corpora
+        if !self.hf_dataset.is_empty() {
+            config.hf_dataset_names = self.hf_dataset.join(",");
+            config.file_path.clear();
+            config.corpora.clear();
+        }
+        if let Some(hf_split) = self.hf_split { config.hf_split = Some(hf_split); }
+        if let Some(hf_cache_dir) = self.hf_cache_dir { config.hf_cache_dir = Some(hf_cache_dir); }
+        if let Some(vocab_size) = self.vocab_size { config.tokenizer_vocab_size = vocab_size; }
+        if let Some(save_path) = self.save_path { config.tokenizer_save_path = save_path; }
+        if let Some(byte_level) = self.byte_level { config.byte_level = byte_level; }
+        if self.record_separator.is_some() || self.record_delimiter.is_some() {
+            config.record_separator = self.record_separator;
+            config.record_delimiter = self.record_delimiter;
+        }
+        if let Some(text_field) = self.text_field { config.text_field = Some(text_field); }
+        if let Some(keep_newlines) = self.keep_newlines { config.keep_newlines = keep_newlines; }
+        if let Some(num_threads) = self.num_threads { config.num_threads = Some(num_threads); }
+        if let Some(invalid_utf8) = self.invalid_utf8 { config.invalid_utf8 = Some(invalid_utf8); }
+        if let Some(steps) = self.normalizer { config.normalizer = Normalizer::new(steps); }
+        // A flag replaces whichever pretokenizer setting the config had
+        if self.pretokenizer.is_some() || self.pretokenizer_pattern.is_some() {
+            config.pretokenizer = self.pretokenizer;
+            config.pretokenizer_pattern = self.pretokenizer_pattern;
+        }
+    }
+}
 
-fn apply_regex() -> Regex {
-    Regex::new(r"'s|'t|'re|'ve|'m|'ll|'d|[\p{L}]+|[\p{N}]+|[^\s\p{L}\p{N}]+|\s+").unwrap()
+#[derive(Args)]
+struct ModelArgs {
+    /// Saved model file or directory [default: tokenizer_save_path from the config]
+    #[arg(long)]
+    model: Option<PathBuf>,
 }
 
-fn pretokenize<'a>(pat: &'a Regex, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
-    pat.find_iter(text).map(|m| m.as_str())
+impl ModelArgs {
+    // The config is only read when no model path is given.
+    fn load(&self, config_flag: Option<&Path>) -> tokenthing::Result<TokenizerModel> {
+        let path = match &self.model {
+            Some(path) => path.clone(),
+            None => PathBuf::from(load_config(&config_path(config_flag))?.tokenizer_save_path),
+        };
+        TokenizerModel::load(&path)
+    }
 }
-fn train_tokenizer(
-    file_path: &str,
-    vocab_size: usize,
-    _seq_len: usize) -> ResultE {
-    // Learned merges and a simple score for merged tokens when discovered
-    let mut merges: Vec<TokenPair> = Vec::new();
-    let mut vocab: HashMap<String, u32> = HashMap::new();
 
-    let re = apply_regex();
+// Input file, decompressed if needed, or stdin when no path (or "-") is given.
+// A Parquet file is read row by row from its text column instead.
+struct Input {
+    name: PathBuf,
+    source: Source,
+}
 
-    // Repeat passes until reaching vocab_size or no pairs remain
-    loop {
-        if merges.len() >= vocab_size { break; }
+enum Source {
+    Lines(Box<dyn BufRead>),
+    Parquet { text_field: Option<String> },
+}
 
-        let file = fs::File::open(file_path)?;
-        let mut reader = BufReader::new(file);
-        let mut line = String::new();
-        let mut global_counts: PairFreqs = PairFreqs::new();
+impl Input {
+    fn open(path: Option<&Path>, text_field: Option<&str>) -> tokenthing::Result<Self> {
+        let path = match path {
+            Some(path) if path != Path::new("-") => path,
+            _ => return Ok(Input { name: PathBuf::from("<stdin>"), source: Source::Lines(Box::new(BufReader::new(std::io::stdin()))) }),
+        };
+        let source = if RecordSeparator::for_path(path) == RecordSeparator::Parquet {
+            Source::Parquet { text_field: text_field.map(str::to_string) }
+        } else {
+            Source::Lines(open_input(path)?)
+        };
+        Ok(Input { name: path.to_path_buf(), source })
+    }
 
-        loop {
+    // Call `f` with every line (without its line ending) or Parquet row, null
+    // rows as empty lines, and its 1-based number.
+    fn for_each_line(self, mut f: impl FnMut(&Path, u64, &[u8]) -> ResultE) -> ResultE {
+        let mut reader = match self.source {
+            Source::Lines(reader) => reader,
+            Source::Parquet { text_field } => {
+                // The reader skips null rows; write them as empty lines so
+                // output lines stay aligned with rows.
+                let (mut next_row, mut failure) = (1, None);
+                let stats = read_documents(&self.name, &RecordSeparator::Parquet, text_field.as_deref(), |row, text| {
+                    let result = (next_row..row).try_for_each(|r| f(&self.name, r, b"")).and_then(|()| f(&self.name, row, text));
+                    next_row = row + 1;
+                    result.map_err(|e| failure = Some(e)).is_ok()
+                })?;
+                if let Some(e) = failure {
+                    return Err(e);
+                }
+                return (next_row..=stats.lines).try_for_each(|r| f(&self.name, r, b""));
+            }
+        };
+        let mut line: Vec<u8> = Vec::new();
+        for line_no in 1.. {
             line.clear();
-            let n = reader.read_line(&mut line)?;
+            let n = reader.read_until(b'\n', &mut line)
+                .map_err(|e| TokenthingError::Io { path: self.name.clone(), line: Some(line_no), source: e })?;
             if n == 0 { break; }
-            if line.ends_with('\n') { line.pop(); if line.ends_with('\r') { line.pop(); } }
+            if line.ends_with(b"\n") { line.pop(); if line.ends_with(b"\r") { line.pop(); } }
+            f(&self.name, line_no, &line)?;
+        }
+        Ok(())
+    }
+}
+
+fn utf8_line<'a>(name: &Path, line_no: u64, line: &'a [u8]) -> tokenthing::Result<&'a str> {
+    std::str::from_utf8(line).map_err(|e| TokenthingError::Utf8 {
+        path: Some(name.to_path_buf()),
+        line: Some(line_no),
+        offset: e.valid_up_to(),
+    })
+}
 
-            let counts = map_count_pairs(&line, &re, &merges);
-            for (k, v) in counts { *global_counts.entry(k).or_insert(0) += v; }
+fn encode_input(tokenizer: &Tokenizer, input: Option<&Path>, text_field: Option<&str>, mut out: impl Write) -> ResultE {
+    Input::open(input, text_field)?.for_each_line(|name, line_no, line| {
+        let ids = if tokenizer.is_byte_level() {
+            tokenizer.encode_bytes(line)
+        } else {
+            tokenizer.encode(utf8_line(name, line_no, line)?)
         }
+        .map_err(|e| format!("{}:{line_no}: {e}", name.display()))?;
+        let ids: Vec<String> = ids.iter().map(u32::to_string).collect();
+        writeln!(out, "{}", ids.join(" "))?;
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(())
+}
 
-        let best = global_counts
-            .iter()
-            .max_by_key(|(_, &freq)| freq)
-            .map(|(pair, &freq)| (pair.clone(), freq));
+fn decode_input(tokenizer: &Tokenizer, input: Option<&Path>, skip_special_tokens: bool, lossy: bool) -> ResultE {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    Input::open(input, None)?.for_each_line(|name, line_no, line| {
+        let ids = utf8_line(name, line_no, line)?
+            .split_whitespace()
+            .map(|id| id.parse::<u32>().map_err(|e| format!("{}:{line_no}: invalid token ID {id:?}: {e}", name.display())))
+            .collect::<Result<Vec<u32>, _>>()?;
+        let text = if lossy {
+            tokenizer.decode_lossy(&ids, skip_special_tokens)
+        } else {
+            tokenizer.decode(&ids, skip_special_tokens)?
+        };
+        writeln!(out, "{text}")?;
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(())
+}
 
-        match best {
-            Some((pair, freq)) if freq > 0 => {
-                let merged = format!("{}{}", pair.0, pair.1);
-                vocab.insert(merged, freq);
-                merges.push(pair);
-            }
-            _ => { println!("No more pairs to merge."); break; }
+fn inspect_model(model: &TokenizerModel, top: usize) -> ResultE {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    writeln!(out, "Format version: {}", model.version)?;
+    writeln!(out, "Mode: {}", if model.byte_level { "byte-level" } else { "char" })?;
+    writeln!(out, "Normalizer: {}", if model.normalizer.is_empty() { "none".to_string() } else { model.normalizer.to_string() })?;
+    writeln!(out, "Pattern: {}", model.pattern)?;
+    writeln!(out, "Vocabulary: {} tokens", model.vocab.len())?;
+    writeln!(out, "Merges: {}", model.merges.len())?;
+    writeln!(out, "Special tokens: {:?}", model.special_tokens)?;
+    if let Some(unk) = &model.unk_token { writeln!(out, "Unknown token: {unk:?}")?; }
+    let meta = &model.metadata;
+    writeln!(out, "Trained on: {} ({} distinct words, {} pretokens)", meta.corpus, meta.words, meta.pretokens)?;
+    writeln!(out, "Target vocab size: {}", meta.vocab_size)?;
+    writeln!(out, "Fingerprint: {}", meta.fingerprint)?;
+
+    writeln!(out, "\nFirst {} merges:", top.min(model.merges.len()))?;
+    for (rank, (a, b)) in model.merges.iter().take(top).enumerate() {
+        writeln!(out, "{rank:>6}  {a:?} + {b:?}")?;
+    }
+    writeln!(out, "\nLast {} tokens:", top.min(model.vocab.len()))?;
+    let first = model.vocab.len().saturating_sub(top);
+    for id in first..model.vocab.len() {
+        writeln!(out, "{id:>6}  {:?}", model.vocab.id_to_token(id as u32).unwrap_or_default())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn run(cli: Cli) -> ResultE {
+    let config_flag = cli.config.as_deref();
+    match cli.command {
+        Command::Train(args) => {
+            let mut config = load_config(&config_path(config_flag))?;
+            args.apply(&mut config);
+            train_tokenizer(&config)?;
+        }
+        Command::Encode { model, input, text_field } => {
+            let tokenizer = Tokenizer::from_model(model.load(config_flag)?)?;
+            encode_input(&tokenizer, input.as_deref(), text_field.as_deref(), BufWriter::new(std::io::stdout().lock()))?;
+        }
+        Command::Decode { model, input, skip_special_tokens, lossy } => {
+            let tokenizer = Tokenizer::from_model(model.load(config_flag)?)?;
+            decode_input(&tokenizer, input.as_deref(), skip_special_tokens, lossy)?;
+        }
+        Command::Inspect { model, top } => {
+            inspect_model(&model.load(config_flag)?, top)?;
         }
     }
 
-    println!("Learned {} merges", merges.len());
     Ok(())
 }
 
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process, sync::Arc};
+    use arrow_array::{RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+    use tokenthing::WordCounts;
 
-fn main() -> ResultE {
-    let config = load_config()?;
-    train_tokenizer(
-        &config.file_path,
-        config.tokenizer_vocab_size,
-        config.tokenizer_sequence_length,
-    )?;
-    
-    Ok(())
+    #[test]
+    fn encode_keeps_null_parquet_rows_as_empty_lines() {
+        let schema = Arc::new(Schema::new(vec![Field::new("text", DataType::Utf8, true)]));
+        let texts = StringArray::from(vec![Some("ab"), None, Some("ba ab"), Some("b"), None]);
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(texts)]).unwrap();
+        let path = env::temp_dir().join(format!("tokenthing-encode-{}.parquet", process::id()));
+        let properties = WriterProperties::builder().set_max_row_group_size(2).build();
+        let mut writer = ArrowWriter::try_new(fs::File::create(&path).unwrap(), schema, Some(properties)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let mut word_counts = WordCounts::new();
+        for word in ["ab", " ab", "ba", "b"] { word_counts.add(word, 1); }
+        let tokenizer = Tokenizer::from_model(Trainer::builder().vocab_size(6).build().unwrap().train(&word_counts)).unwrap();
+        let mut out = Vec::new();
+        let result = encode_input(&tokenizer, Some(&path), None, &mut out);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let ids = |text: &str| tokenizer.encode(text).unwrap().iter().map(u32::to_string).collect::<Vec<_>>().join(" ");
+        let expected = [ids("ab"), String::new(), ids("ba ab"), ids("b"), String::new()];
+        assert_eq!(String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(), expected);
+    }
 }
@@ -0,0 +1,229 @@
+use std::{fmt, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::one_or_many, expand_inputs, hf_dataset_files, CorpusStats, Result, TokenthingError};
+
+/// One corpus of a training mix: files, or a Hugging Face dataset split read
+/// from the local cache. Its pretoken counts are multiplied by `weight`, or
+/// scaled so it makes up `share` of the mixed counts; without either it keeps
+/// its natural size (weight 1).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Corpus {
+    /// One path or a list of paths, glob patterns and directories
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub file_path: Vec<String>,
+    /// Hugging Face dataset name, e.g. `roneneldan/TinyStories`, instead of `file_path`
+    #[serde(default)]
+    pub hf_dataset: Option<String>,
+    /// Split of `hf_dataset`; defaults to train
+    #[serde(default)]
+    pub split: Option<String>,
+    #[serde(default)]
+    pub weight: Option<f64>,
+    /// Fraction of the mixed training text, between 0 and 1. When every
+    /// corpus has a share, the shares must add up to 1.
+    #[serde(default)]
+    pub share: Option<f64>,
+}
+
+impl Corpus {
+    pub fn new(file_path: Vec<String>) -> Self {
+        Corpus { file_path, hf_dataset: None, split: None, weight: None, share: None }
+    }
+
+    /// A corpus of a cached Hugging Face dataset split; `None` means train.
+    pub fn hf(name: impl Into<String>, split: Option<String>) -> Self {
+        Corpus { file_path: Vec::new(), hf_dataset: Some(name.into()), split, weight: None, share: None }
+    }
+
+    fn split(&self) -> &str {
+        self.split.as_deref().unwrap_or("train")
+    }
+
+    /// The corpus files: `file_path` expanded, or the cached shards of
+    /// `hf_dataset` under `hf_cache_dir` (default `$HF_HOME`).
+    pub fn files(&self, hf_cache_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+        self.validate()?;
+        match &self.hf_dataset {
+            Some(name) => hf_dataset_files(name, self.split(), hf_cache_dir),
+            None => expand_inputs(&self.file_path),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        let message = match (self.weight, self.share) {
+            _ if self.file_path.is_empty() && self.hf_dataset.is_none() => "corpus without file_path or hf_dataset".to_string(),
+            _ if !self.file_path.is_empty() && self.hf_dataset.is_some() => {
+                format!("corpus {self}: set either file_path or hf_dataset, not both")
+            }
+            _ if self.split.is_some() && self.hf_dataset.is_none() => format!("corpus {self}: split needs hf_dataset"),
+            (Some(_), Some(_)) => format!("corpus {self}: set either weight or share, not both"),
+            (Some(weight), None) if !(weight > 0.0 && weight.is_finite()) => format!("corpus {self}: weight must be positive"),
+            (None, Some(share)) if !(share > 0.0 && share <= 1.0) => format!("corpus {self}: share must be in (0, 1]"),
+            _ => return Ok(()),
+        };
+        Err(TokenthingError::Config { path: None, message })
+    }
+}
+
+impl fmt::Display for Corpus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hf_dataset {
+            Some(name) if self.file_path.is_empty() => write!(f, "{name} [{}]", self.split())?,
+            _ => f.write_str(&self.file_path.join(", "))?,
+        }
+        match (self.weight, self.share) {
+            (Some(weight), _) => write!(f, " (weight {weight})"),
+            (_, Some(share)) => write!(f, " (share {share})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How one corpus of a mix was read and scaled.
+#[derive(Debug, Clone)]
+pub struct CorpusReport {
+    /// The corpus files, with their stats
+    pub files: Vec<(PathBuf, CorpusStats)>,
+    /// Pretokens counted before scaling
+    pub pretokens: u64,
+    /// Factor applied to the corpus's counts
+    pub scale: f64,
+}
+
+// Check that the shares of a mix add up, before any corpus is read: to at
+// most 1, leaving room for corpora without a share, and to exactly 1 when
+// every corpus has one, as they would otherwise be rescaled to fill the mix.
+pub(crate) fn check_shares(corpora: &[Corpus]) -> Result<()> {
+    let share_sum: f64 = corpora.iter().filter_map(|c| c.share).sum();
+    let all_shares = corpora.iter().all(|c| c.share.is_some());
+    let message = if share_sum > 1.0 + 1e-9 {
+        format!("corpus shares add up to {share_sum}, more than 1")
+    } else if all_shares && share_sum < 1.0 - 1e-9 {
+        format!("corpus shares add up to {share_sum}; when every corpus has a share they must add up to 1")
+    } else if !all_shares && share_sum >= 1.0 - 1e-9 {
+        format!("corpus shares add up to {share_sum}, leaving nothing for corpora without a share")
+    } else {
+        return Ok(());
+    };
+    Err(TokenthingError::Config { path: None, message })
+}
+
+// Scale factors for corpora with the given pretoken totals. Shares are fixed
+// fractions of the mix; weighted corpora split the rest by weight times size.
+// Only the ratios matter to BPE, so the factors are divided by the smallest
+// one: no factor is below 1, so no word's count rounds to 0 and drops out.
+// Scaled counts saturate rather than overflow.
+pub(crate) fn mix_factors(corpora: &[Corpus], totals: &[u64]) -> Result<Vec<f64>> {
+    let error = |message: String| Err(TokenthingError::Config { path: None, message });
+    check_shares(corpora)?;
+    let share_sum: f64 = corpora.iter().filter_map(|c| c.share).sum();
+    let weighted_mass: f64 = corpora
+        .iter()
+        .zip(totals)
+        .filter(|(c, _)| c.share.is_none())
+        .map(|(c, &total)| c.weight.unwrap_or(1.0) * total as f64)
+        .sum();
+    if !weighted_mass.is_finite() {
+        return error("corpus weights are too large".to_string());
+    }
+    let has_weighted = weighted_mass > 0.0;
+
+    let factors: Vec<f64> = corpora
+        .iter()
+        .zip(totals)
+        .map(|(c, &total)| match c.share {
+            // An empty corpus has nothing to scale
+            _ if total == 0 => 1.0,
+            Some(share) if has_weighted => share * weighted_mass / ((1.0 - share_sum) * total as f64),
+            Some(share) => share / total as f64,
+            None => c.weight.unwrap_or(1.0),
+        })
+        .collect();
+    let min = factors.iter().zip(totals).filter(|(_, &total)| total > 0).map(|(&f, _)| f).fold(f64::INFINITY, f64::min);
+    if !min.is_finite() || min <= 0.0 {
+        return Ok(vec![1.0; corpora.len()]);
+    }
+    Ok(factors.into_iter().map(|f| f / min).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WordCounts;
+
+    fn corpus(weight: Option<f64>, share: Option<f64>) -> Corpus {
+        Corpus { weight, share, ..Corpus::new(vec!["corpus.txt".to_string()]) }
+    }
+
+    // Fraction of the mixed counts each corpus ends up with
+    fn fractions(corpora: &[Corpus], totals: &[u64]) -> Vec<f64> {
+        let scaled: Vec<f64> = mix_factors(corpora, totals).unwrap().iter().zip(totals).map(|(f, &t)| f * t as f64).collect();
+        let sum: f64 = scaled.iter().sum();
+        scaled.iter().map(|s| s / sum).collect()
+    }
+
+    #[test]
+    fn scaled_shares_match_declared_shares() {
+        let corpora = [corpus(None, None), corpus(Some(3.0), None), corpus(None, Some(0.25))];
+        let totals = [1000, 200, 100];
+        let got = fractions(&corpora, &totals);
+        // The weighted corpora split the remaining 0.75 by weight times size: 1000 to 600
+        for (got, want) in got.iter().zip([0.75 * 1000.0 / 1600.0, 0.75 * 600.0 / 1600.0, 0.25]) {
+            assert!((got - want).abs() < 1e-12, "{got} != {want}");
+        }
+
+        let corpora = [corpus(None, Some(0.6)), corpus(None, Some(0.4))];
+        let got = fractions(&corpora, &[500, 2000]);
+        assert!((got[0] - 0.6).abs() < 1e-12 && (got[1] - 0.4).abs() < 1e-12, "{got:?}");
+    }
+
+    #[test]
+    fn mixing_keeps_every_word() {
+        let corpora = [corpus(None, None), corpus(Some(3.0), None), corpus(None, Some(0.01))];
+        let tables: Vec<WordCounts> = [("prose", 5000), ("code", 200), ("rare", 10)]
+            .iter()
+            .map(|&(prefix, words)| {
+                let mut word_counts = WordCounts::new();
+                for i in 0..words { word_counts.add(&format!("{prefix}{i}"), 1 + i % 3); }
+                word_counts
+            })
+            .collect();
+        let totals: Vec<u64> = tables.iter().map(WordCounts::total).collect();
+        let factors = mix_factors(&corpora, &totals).unwrap();
+        assert!(factors.iter().all(|&f| f >= 1.0), "{factors:?}");
+        assert!(factors.contains(&1.0));
+
+        let mut mixed = WordCounts::new();
+        let words: usize = tables.iter().map(WordCounts::len).sum();
+        for (mut word_counts, factor) in tables.into_iter().zip(factors) {
+            word_counts.scale(factor);
+            mixed.merge(word_counts);
+        }
+        assert_eq!(mixed.len(), words);
+    }
+
+    #[test]
+    fn extreme_factors_saturate() {
+        let factors = mix_factors(&[corpus(Some(1e-20), None), corpus(Some(1.0), None)], &[1000, 10]).unwrap();
+        assert_eq!(factors[0], 1.0);
+        assert!(factors[1] > 1e19);
+
+        let mut word_counts = WordCounts::new();
+        word_counts.add("a", u64::MAX - 1);
+        word_counts.add("a", 5);
+        word_counts.add("b", 7);
+        assert_eq!(word_counts.total(), u64::MAX);
+        word_counts.scale(1e30);
+        assert_eq!(word_counts.iter().find(|(w, _)| *w == "b").map(|(_, c)| c), Some(u64::MAX));
+    }
+
+    #[test]
+    fn shares_must_add_up() {
+        let reject = |corpora: &[Corpus]| mix_factors(corpora, &vec![100; corpora.len()]).unwrap_err().to_string();
+        assert!(reject(&[corpus(None, Some(0.7)), corpus(None, Some(0.5))]).contains("more than 1"));
+        assert!(reject(&[corpus(None, Some(0.5)), corpus(None, Some(0.2))]).contains("must add up to 1"));
+        assert!(reject(&[corpus(None, Some(1.0)), corpus(None, None)]).contains("leaving nothing"));
+        assert!(mix_factors(&[corpus(None, Some(0.5)), corpus(None, Some(0.5))], &[100, 300]).is_ok());
+    }
+}
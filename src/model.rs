@@ -0,0 +1,116 @@
+use std::{fs, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+
+use crate::{Normalizer, Result, TokenPair, TokenthingError, Vocab};
+
+/// Model file format version; bump on incompatible changes.
+pub const MODEL_VERSION: u32 = 1;
+/// File written inside the save directory.
+pub const MODEL_FILE_NAME: &str = "tokenizer.yaml";
+
+/// How a saved model was trained.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TrainingMetadata {
+    pub corpus: String,
+    pub vocab_size: usize,
+    #[serde(default)]
+    pub alphabet_size: usize,
+    pub words: usize,
+    pub pretokens: u64,
+    /// Hex fingerprint of the settings and merges; equal runs give equal values
+    pub fingerprint: String,
+}
+
+/// A trained tokenizer as written to disk.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenizerModel {
+    pub version: u32,
+    pub byte_level: bool,
+    /// Applied to input before pretokenization; empty for none
+    #[serde(default)]
+    pub normalizer: Normalizer,
+    pub pattern: String,
+    /// Tokens matched verbatim and never byte-mapped or merged
+    #[serde(default)]
+    pub special_tokens: Vec<String>,
+    /// Special token standing in for chars outside the vocabulary
+    #[serde(default)]
+    pub unk_token: Option<String>,
+    /// In rank order: earlier merges apply first
+    pub merges: Vec<TokenPair>,
+    pub vocab: Vocab,
+    pub metadata: TrainingMetadata,
+}
+
+impl TokenizerModel {
+    /// Write the model to `dir/tokenizer.yaml`, creating `dir` if needed.
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| TokenthingError::io(dir, e))?;
+        let path = dir.join(MODEL_FILE_NAME);
+        let yaml = serde_yaml::to_string(self)
+            .map_err(|e| TokenthingError::ModelFormat { path: path.clone(), message: e.to_string() })?;
+        fs::write(&path, yaml).map_err(|e| TokenthingError::io(&path, e))?;
+        Ok(path)
+    }
+
+    /// Read a model from a file, or from `tokenizer.yaml` inside a directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let mut path = path.as_ref().to_path_buf();
+        if path.is_dir() { path.push(MODEL_FILE_NAME); }
+        let yaml = fs::read_to_string(&path).map_err(|e| TokenthingError::io(&path, e))?;
+        let model: TokenizerModel = serde_yaml::from_str(&yaml)
+            .map_err(|e| TokenthingError::ModelFormat { path: path.clone(), message: e.to_string() })?;
+        if model.version != MODEL_VERSION {
+            let message = format!("unsupported model version {} (expected {MODEL_VERSION})", model.version);
+            return Err(TokenthingError::ModelFormat { path, message });
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, env, process};
+    use crate::{SpecialTokens, Tokenizer, Trainer, WordCounts};
+
+    // Tokens YAML would read back as null, booleans or numbers, or mangle as whitespace
+    const TRICKY_WORDS: [(&str, u64); 9] =
+        [("null", 50), ("~", 40), ("\r\n", 30), ("\n", 30), (" ", 20), ("true", 20), ("no", 20), ("1.5", 10), ("0x1F", 10)];
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut word_counts = WordCounts::new();
+        for (word, count) in TRICKY_WORDS { word_counts.add(word, count); }
+        let special_tokens = SpecialTokens { unk: Some("~unk~".to_string()), additional: vec!["null ".to_string()], ..Default::default() };
+        let trainer = Trainer::builder().vocab_size(40).special_tokens(special_tokens).build().unwrap();
+        let model = trainer.train(&word_counts);
+        for (word, _) in TRICKY_WORDS {
+            assert!(model.vocab.token_to_id(word).is_some(), "{word:?} not learned");
+        }
+
+        let dir = env::temp_dir().join(format!("tokenthing-model-{}", process::id()));
+        let path = model.save(&dir).unwrap();
+        let loaded = TokenizerModel::load(&dir).unwrap();
+        assert_eq!(BTreeMap::from(loaded.vocab.clone()), BTreeMap::from(model.vocab.clone()));
+        assert_eq!(loaded.merges, model.merges);
+        assert_eq!(loaded.special_tokens, model.special_tokens);
+        assert_eq!(loaded.unk_token, model.unk_token);
+        assert_eq!(loaded.metadata.fingerprint, model.metadata.fingerprint);
+
+        let text = "null ~ true\r\nno 1.5\n0x1F";
+        let (original, reloaded) = (Tokenizer::from_model(model).unwrap(), Tokenizer::from_model(loaded).unwrap());
+        let ids = reloaded.encode(text).unwrap();
+        assert_eq!(ids, original.encode(text).unwrap());
+        assert_eq!(reloaded.decode(&ids, false).unwrap(), text);
+
+        let yaml = fs::read_to_string(&path).unwrap();
+        fs::write(&path, yaml.replacen(&format!("version: {MODEL_VERSION}"), "version: 99", 1)).unwrap();
+        match TokenizerModel::load(&path) {
+            Err(TokenthingError::ModelFormat { message, .. }) => assert!(message.contains("unsupported model version 99"), "{message}"),
+            other => panic!("expected a version error, got {other:?}"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// One text transformation applied before pretokenization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizerStep {
+    /// Canonical composition
+    Nfc,
+    /// Compatibility composition: also folds full-width forms, ligatures, etc.
+    Nfkc,
+    /// Canonical decomposition
+    Nfd,
+    Lowercase,
+    /// Drop combining marks (é becomes e); the rest stays composed
+    StripAccents,
+    /// Drop control characters other than tab, newline and carriage return
+    StripControl,
+}
+
+impl FromStr for NormalizerStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(NormalizerStep::Nfc),
+            "nfkc" => Ok(NormalizerStep::Nfkc),
+            "nfd" => Ok(NormalizerStep::Nfd),
+            "lowercase" => Ok(NormalizerStep::Lowercase),
+            "strip_accents" => Ok(NormalizerStep::StripAccents),
+            "strip_control" => Ok(NormalizerStep::StripControl),
+            _ => Err(format!(
+                "unknown normalizer step {s:?} (expected nfc, nfkc, nfd, lowercase, strip_accents or strip_control)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NormalizerStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NormalizerStep::Nfc => "nfc",
+            NormalizerStep::Nfkc => "nfkc",
+            NormalizerStep::Nfd => "nfd",
+            NormalizerStep::Lowercase => "lowercase",
+            NormalizerStep::StripAccents => "strip_accents",
+            NormalizerStep::StripControl => "strip_control",
+        })
+    }
+}
+
+impl NormalizerStep {
+    fn apply(self, text: &str) -> String {
+        match self {
+            NormalizerStep::Nfc => text.nfc().collect(),
+            NormalizerStep::Nfkc => text.nfkc().collect(),
+            NormalizerStep::Nfd => text.nfd().collect(),
+            NormalizerStep::Lowercase => text.to_lowercase(),
+            NormalizerStep::StripAccents => text.nfd().filter(|&c| !is_combining_mark(c)).nfc().collect(),
+            NormalizerStep::StripControl => text.chars().filter(|&c| !c.is_control() || matches!(c, '\t' | '\n' | '\r')).collect(),
+        }
+    }
+}
+
+/// Steps applied in order to every line before pretokenization, in training
+/// and encoding alike. Empty by default, leaving text untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Normalizer {
+    steps: Vec<NormalizerStep>,
+}
+
+impl Normalizer {
+    pub fn new(steps: Vec<NormalizerStep>) -> Self {
+        Normalizer { steps }
+    }
+
+    pub fn steps(&self) -> &[NormalizerStep] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for step in &self.steps {
+            text = Cow::Owned(step.apply(&text));
+        }
+        text
+    }
+
+    /// Byte-level counterpart of `normalize`: valid UTF-8 runs are normalized,
+    /// invalid bytes pass through unchanged.
+    pub fn normalize_bytes<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.is_empty() {
+            return Cow::Borrowed(bytes);
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        for chunk in bytes.utf8_chunks() {
+            out.extend_from_slice(self.normalize(chunk.valid()).as_bytes());
+            out.extend_from_slice(chunk.invalid());
+        }
+        Cow::Owned(out)
+    }
+}
+
+impl fmt::Display for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps: Vec<String> = self.steps.iter().map(NormalizerStep::to_string).collect();
+        f.write_str(&steps.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(steps: &[NormalizerStep], text: &str) -> String {
+        Normalizer::new(steps.to_vec()).normalize(text).into_owned()
+    }
+
+    #[test]
+    fn strip_accents_keeps_text_composed() {
+        // Precomposed and decomposed é both lose the accent
+        assert_eq!(normalize(&[NormalizerStep::StripAccents], "caf\u{e9} cafe\u{301} na\u{ef}ve"), "cafe cafe naive");
+        // Hangul syllables decompose into jamo, which are not marks, and are recomposed
+        assert_eq!(normalize(&[NormalizerStep::StripAccents], "\u{d55c}\u{f1}"), "\u{d55c}n");
+        assert_eq!(normalize(&[NormalizerStep::Nfd], "\u{e9}"), "e\u{301}");
+        assert_eq!(normalize(&[NormalizerStep::Nfc], "e\u{301}"), "\u{e9}");
+    }
+
+    #[test]
+    fn nfkc_folds_compatibility_forms() {
+        assert_eq!(normalize(&[NormalizerStep::Nfkc], "\u{ff34}\u{ff4f}\u{ff4b}\u{ff45}\u{ff4e} \u{fb01}"), "Token fi");
+        assert_eq!(normalize(&[NormalizerStep::Nfc], "\u{ff34}"), "\u{ff34}");
+        let steps = [NormalizerStep::Nfkc, NormalizerStep::Lowercase, NormalizerStep::StripControl];
+        assert_eq!(normalize(&steps, "\u{ff34}OK\u{7}\tEN\n"), "tok\ten\n");
+    }
+
+    #[test]
+    fn normalize_bytes_keeps_invalid_bytes() {
+        let normalizer = Normalizer::new(vec![NormalizerStep::Lowercase]);
+        assert_eq!(normalizer.normalize_bytes(b"AB\xffC\xe9\xbfD"), &b"ab\xffc\xe9\xbfd"[..]);
+        assert!(matches!(Normalizer::default().normalize_bytes(b"A\xff"), Cow::Borrowed(b"A\xff")));
+    }
+}
@@ -0,0 +1,115 @@
+use fancy_regex::Regex;
+
+use crate::{Result, TokenthingError};
+
+/// Splits text into pretokens. BPE merges never cross a pretoken boundary.
+#[derive(Debug, Clone)]
+pub struct Pretokenizer {
+    regex: Regex,
+}
+
+impl Pretokenizer {
+    /// Contractions, letter runs, digit runs, punctuation runs and whitespace runs.
+    pub const DEFAULT_PATTERN: &'static str = r"'s|'t|'re|'ve|'m|'ll|'d|[\p{L}]+|[\p{N}]+|[^\s\p{L}\p{N}]+|\s+";
+    /// GPT-2: words keep their leading space, and a whitespace run leaves its
+    /// last space to the word after it.
+    pub const GPT2_PATTERN: &'static str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+    /// cl100k (GPT-3.5/4): case-insensitive contractions, digits in groups of
+    /// at most three, and newlines split from other whitespace.
+    pub const CL100K_PATTERN: &'static str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+    /// Llama 3 splits like cl100k.
+    pub const LLAMA3_PATTERN: &'static str = Self::CL100K_PATTERN;
+
+    /// Named patterns accepted by [`Pretokenizer::preset`].
+    pub const PRESETS: &'static [(&'static str, &'static str)] = &[
+        ("default", Self::DEFAULT_PATTERN),
+        ("gpt2", Self::GPT2_PATTERN),
+        ("cl100k", Self::CL100K_PATTERN),
+        ("llama3", Self::LLAMA3_PATTERN),
+    ];
+
+    /// Compile a custom pattern. Lookaround such as `\s+(?!\S)` is supported.
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(Pretokenizer { regex: Regex::new(pattern)? })
+    }
+
+    /// One of the named [`PRESETS`](Self::PRESETS).
+    pub fn preset(name: &str) -> Result<Self> {
+        match Self::PRESETS.iter().find(|(preset, _)| *preset == name) {
+            Some((_, pattern)) => Self::new(pattern),
+            None => {
+                let names: Vec<&str> = Self::PRESETS.iter().map(|(preset, _)| *preset).collect();
+                let message = format!("unknown pretokenizer {name:?}, expected one of {}", names.join(", "));
+                Err(TokenthingError::Config { path: None, message })
+            }
+        }
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Yields an error, and nothing after it, if the pattern hits the engine's
+    /// backtracking limit, which only pathological custom patterns do.
+    pub fn split<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Result<&'a str>> + 'a {
+        let mut failed = false;
+        self.regex.find_iter(text).map_while(move |m| {
+            if failed { return None; }
+            failed = m.is_err();
+            Some(m.map(|m| m.as_str()).map_err(TokenthingError::from))
+        })
+    }
+
+    /// Pretokenize raw bytes: valid UTF-8 runs go through the pattern, each
+    /// invalid byte run becomes a pretoken of its own so nothing is dropped.
+    pub fn split_bytes<'a>(&'a self, bytes: &'a [u8]) -> impl Iterator<Item = Result<&'a [u8]>> + 'a {
+        bytes.utf8_chunks().flat_map(move |chunk| {
+            let invalid = chunk.invalid();
+            self.split(chunk.valid())
+                .map(|pretoken| pretoken.map(str::as_bytes))
+                .chain((!invalid.is_empty()).then_some(Ok(invalid)))
+        })
+    }
+}
+
+impl Default for Pretokenizer {
+    fn default() -> Self {
+        Pretokenizer::new(Self::DEFAULT_PATTERN).expect("default pattern is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(pretokenizer: &Pretokenizer, text: &str) -> Vec<String> {
+        pretokenizer.split(text).map(|pretoken| pretoken.unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn presets_match_reference_splits() {
+        let text = "Hello  world's 12345\n\n";
+        // References from tiktoken's gpt2 and cl100k_base encodings
+        let cases: [(&str, &[&str]); 4] = [
+            ("default", &["Hello", "  ", "world", "'s", " ", "12345", "\n\n"]),
+            ("gpt2", &["Hello", " ", " world", "'s", " 12345", "\n\n"]),
+            ("cl100k", &["Hello", " ", " world", "'s", " ", "123", "45", "\n\n"]),
+            ("llama3", &["Hello", " ", " world", "'s", " ", "123", "45", "\n\n"]),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(split(&Pretokenizer::preset(name).unwrap(), text), expected, "preset {name}");
+        }
+        let cl100k = Pretokenizer::preset("cl100k").unwrap();
+        assert_eq!(split(&cl100k, "I'M here!\r\n  ok"), ["I", "'M", " here", "!\r\n", " ", " ok"]);
+    }
+
+    #[test]
+    fn backtrack_limit_is_an_error() {
+        // Nested quantifiers behind a lookahead backtrack exponentially
+        let pretokenizer = Pretokenizer::new(r"(?:a+)+(?=b)|\s+").unwrap();
+        let text = format!(" {}c", "a".repeat(40));
+        let pretokens: Vec<Result<&str>> = pretokenizer.split(&text).collect();
+        assert!(matches!(pretokens.last(), Some(Err(TokenthingError::Regex(fancy_regex::Error::RuntimeError(_))))), "{pretokens:?}");
+        assert!(pretokens[..pretokens.len() - 1].iter().all(Result::is_ok));
+    }
+}
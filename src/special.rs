@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, TokenthingError};
+
+/// Reserved tokens declared in the config. They take the first IDs, in the
+/// order bos, eos, pad, unk, then `additional`. In input they are matched
+/// verbatim before normalization and pretokenization, and they are never
+/// counted, split or merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SpecialTokens {
+    #[serde(default)]
+    pub bos: Option<String>,
+    #[serde(default)]
+    pub eos: Option<String>,
+    #[serde(default)]
+    pub pad: Option<String>,
+    /// Stands in for chars outside a char-mode vocabulary, which are otherwise dropped
+    #[serde(default)]
+    pub unk: Option<String>,
+    /// Further control tokens, e.g. `<|user|>`
+    #[serde(default)]
+    pub additional: Vec<String>,
+}
+
+impl SpecialTokens {
+    /// Every declared token, in ID order.
+    pub fn tokens(&self) -> Vec<String> {
+        [&self.bos, &self.eos, &self.pad, &self.unk]
+            .into_iter()
+            .flatten()
+            .chain(&self.additional)
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens().is_empty()
+    }
+
+    // Tokens must be non-empty and distinct, or IDs would silently collapse.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for token in self.tokens() {
+            let message = if token.is_empty() {
+                "special tokens must not be empty".to_string()
+            } else if !seen.insert(token.clone()) {
+                format!("special token {token:?} declared twice")
+            } else {
+                continue;
+            };
+            return Err(TokenthingError::Config { path: None, message });
+        }
+        Ok(())
+    }
+}
+
+// Cuts special tokens out of input: the leftmost occurrence wins, and the
+// longest token when several start at the same place.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpecialSplitter {
+    regex: Option<Regex>,
+}
+
+impl SpecialSplitter {
+    pub(crate) fn new(tokens: &[String]) -> Self {
+        if tokens.is_empty() {
+            return SpecialSplitter::default();
+        }
+        let mut tokens: Vec<&String> = tokens.iter().collect();
+        tokens.sort_by_key(|t| std::cmp::Reverse(t.len()));
+        let alternation: Vec<String> = tokens.iter().map(|t| fancy_regex::escape(t).into_owned()).collect();
+        let regex = Regex::new(&alternation.join("|")).expect("escaped literals are a valid pattern");
+        SpecialSplitter { regex: Some(regex) }
+    }
+
+    // The pieces of `text` in order, each flagged true if it is a special token.
+    pub(crate) fn split<'a>(&self, text: &'a str) -> Vec<(&'a str, bool)> {
+        let Some(regex) = &self.regex else { return vec![(text, false)]; };
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for m in regex.find_iter(text).map_while(|m| m.ok()) {
+            if m.start() > start { pieces.push((&text[start..m.start()], false)); }
+            pieces.push((m.as_str(), true));
+            start = m.end();
+        }
+        if start < text.len() || pieces.is_empty() { pieces.push((&text[start..], false)); }
+        pieces
+    }
+
+    // Byte-level counterpart of `split`; invalid UTF-8 is never special.
+    pub(crate) fn split_bytes<'a>(&self, bytes: &'a [u8]) -> Vec<(&'a [u8], bool)> {
+        if self.regex.is_none() {
+            return vec![(bytes, false)];
+        }
+        let mut pieces = Vec::new();
+        for chunk in bytes.utf8_chunks() {
+            pieces.extend(self.split(chunk.valid()).into_iter().map(|(piece, special)| (piece.as_bytes(), special)));
+            if !chunk.invalid().is_empty() { pieces.push((chunk.invalid(), false)); }
+        }
+        pieces
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::{HashMap, HashSet}, path::Path};
+
+use crate::{
+    byte_level::{split_bytes, split_chars, unicode_to_byte},
+    special::SpecialSplitter,
+    Normalizer, Pretokenizer, Result, TokenPair, TokenizerModel, TokenthingError, Vocab,
+};
+
+// Merge ranks: position of each pair in the learned merge list
+pub(crate) type Ranks = HashMap<TokenPair, usize>;
+
+// Apply learned merges to the symbols of one pretoken, standard BPE style:
+// repeatedly merge the adjacent pair with the lowest rank (every occurrence,
+// left to right) until no known pair remains. This replays the training
+// merges in order, so training words come out as the trainer segmented them.
+pub(crate) fn apply_merges_to_tokens(mut tokens: Vec<String>, ranks: &Ranks) -> Vec<String> {
+    if ranks.is_empty() || tokens.len() < 2 {
+        return tokens;
+    }
+
+    loop {
+        let best = tokens
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, w)| ranks.get(&(w[0].clone(), w[1].clone())).map(|&rank| (rank, i)))
+            .min();
+        let Some((_, at)) = best else { break; };
+        let (a, b) = (tokens[at].clone(), tokens[at + 1].clone());
+
+        let mut merged = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            if i + 1 < tokens.len() && tokens[i] == a && tokens[i + 1] == b {
+                merged.push(format!("{a}{b}"));
+                i += 2;
+            } else {
+                merged.push(std::mem::take(&mut tokens[i]));
+                i += 1;
+            }
+        }
+        tokens = merged;
+        if tokens.len() < 2 { break; }
+    }
+
+    tokens
+}
+
+/// A trained tokenizer, ready to encode and decode.
+pub struct Tokenizer {
+    byte_level: bool,
+    normalizer: Normalizer,
+    pretokenizer: Pretokenizer,
+    ranks: Ranks,
+    vocab: Vocab,
+    specials: SpecialSplitter,
+    special_ids: HashSet<u32>,
+    unk_id: Option<u32>,
+}
+
+impl Tokenizer {
+    pub(crate) fn new(
+        byte_level: bool,
+        normalizer: Normalizer,
+        pretokenizer: Pretokenizer,
+        merges: &[TokenPair],
+        vocab: Vocab,
+        special_tokens: &[String],
+        unk_token: Option<&str>,
+    ) -> Self {
+        let ranks = merges.iter().enumerate().map(|(rank, pair)| (pair.clone(), rank)).collect();
+        let specials = SpecialSplitter::new(special_tokens);
+        let special_ids = special_tokens.iter().filter_map(|t| vocab.token_to_id(t)).collect();
+        let unk_id = unk_token.and_then(|t| vocab.token_to_id(t));
+        Tokenizer { byte_level, normalizer, pretokenizer, ranks, vocab, specials, special_ids, unk_id }
+    }
+
+    pub fn from_model(model: TokenizerModel) -> Result<Self> {
+        let pretokenizer = Pretokenizer::new(&model.pattern)?;
+        Ok(Tokenizer::new(model.byte_level, model.normalizer, pretokenizer, &model.merges, model.vocab, &model.special_tokens, model.unk_token.as_deref()))
+    }
+
+    /// Load a saved model file or directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Tokenizer::from_model(TokenizerModel::load(path)?)
+    }
+
+    pub fn is_byte_level(&self) -> bool {
+        self.byte_level
+    }
+
+    pub fn vocab(&self) -> &Vocab {
+        &self.vocab
+    }
+
+    pub fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    /// Encode text to token IDs. Special tokens in the text map straight to
+    /// their IDs; the rest is normalized and pretokenized. In char mode, chars
+    /// outside the vocabulary become the unk token, or are dropped without one;
+    /// byte-level mode covers every input. Fails only if the pretokenizer
+    /// pattern hits its backtracking limit.
+    pub fn encode(&self, text: &str) -> Result<Vec<u32>> {
+        if self.byte_level {
+            return self.encode_bytes(text.as_bytes());
+        }
+        let mut ids = Vec::new();
+        for (piece, special) in self.specials.split(text) {
+            if special {
+                ids.extend(self.vocab.token_to_id(piece));
+                continue;
+            }
+            let piece = self.normalizer.normalize(piece);
+            for pretoken in self.pretokenizer.split(&piece) {
+                let tokens = self.encode_word(split_chars(pretoken?));
+                ids.extend(tokens.iter().filter_map(|token| self.vocab.token_to_id(token).or(self.unk_id)));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Byte-level counterpart of `encode`. Every byte has a base symbol, so any
+    /// input (invalid UTF-8 and rare scripts included) encodes without unknowns.
+    /// In char mode the bytes must be valid UTF-8 and are encoded as text.
+    pub fn encode_bytes(&self, bytes: &[u8]) -> Result<Vec<u32>> {
+        if !self.byte_level {
+            let text = std::str::from_utf8(bytes).map_err(|e| TokenthingError::Utf8 { path: None, line: None, offset: e.valid_up_to() })?;
+            return self.encode(text);
+        }
+        let mut ids = Vec::new();
+        for (piece, special) in self.specials.split_bytes(bytes) {
+            if special {
+                ids.extend(std::str::from_utf8(piece).ok().and_then(|t| self.vocab.token_to_id(t)));
+                continue;
+            }
+            let piece = self.normalizer.normalize_bytes(piece);
+            for pretoken in self.pretokenizer.split_bytes(&piece) {
+                let tokens = self.encode_word(split_bytes(pretoken?));
+                ids.extend(tokens.iter().filter_map(|token| self.vocab.token_to_id(token)));
+            }
+        }
+        Ok(ids)
+    }
+
+    // Apply the merges to the base symbols of one pretoken.
+    pub(crate) fn encode_word(&self, symbols: Vec<String>) -> Vec<String> {
+        apply_merges_to_tokens(symbols, &self.ranks)
+    }
+
+    /// Decode token IDs to text. Fails on an unknown ID or, in byte-level mode,
+    /// when the bytes are not valid UTF-8.
+    pub fn decode(&self, ids: &[u32], skip_special_tokens: bool) -> Result<String> {
+        let mut bytes = Vec::new();
+        for &id in ids {
+            let token = self.vocab.id_to_token(id).ok_or(TokenthingError::UnknownTokenId(id))?;
+            self.push_token_bytes(id, token, skip_special_tokens, &mut bytes);
+        }
+        String::from_utf8(bytes).map_err(|e| TokenthingError::Utf8 { path: None, line: None, offset: e.utf8_error().valid_up_to() })
+    }
+
+    /// Like `decode`, but unknown IDs and invalid UTF-8 become U+FFFD.
+    pub fn decode_lossy(&self, ids: &[u32], skip_special_tokens: bool) -> String {
+        let mut bytes = Vec::new();
+        for &id in ids {
+            match self.vocab.id_to_token(id) {
+                Some(token) => self.push_token_bytes(id, token, skip_special_tokens, &mut bytes),
+                None => bytes.extend_from_slice(char::REPLACEMENT_CHARACTER.to_string().as_bytes()),
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    // Append the raw bytes of one token. Byte-level symbols map back to their
+    // bytes; special tokens and char-mode tokens are their own UTF-8 text.
+    fn push_token_bytes(&self, id: u32, token: &str, skip_special_tokens: bool, out: &mut Vec<u8>) {
+        let special = self.special_ids.contains(&id);
+        if special && skip_special_tokens {
+            return;
+        }
+        if !self.byte_level || special {
+            out.extend_from_slice(token.as_bytes());
+            return;
+        }
+        let table = unicode_to_byte();
+        for c in token.chars() {
+            match table.get(&c) {
+                Some(&b) => out.push(b),
+                None => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trainer::learn_merges, WordCounts};
+
+    const CORPUS: &str = "the cat sat on the mat, the dog sat on the log\n\
+                          a cat and a dog ran to the other cat\n\
+                          aaaa aaa banana bandana";
+
+    fn tokenizer_for(text: &str, vocab_size: usize) -> (WordCounts, Tokenizer) {
+        let re = Pretokenizer::default();
+        let mut word_counts = WordCounts::new();
+        word_counts.add_text(&re, text).unwrap();
+        let (alphabet, merges) = learn_merges(&word_counts, vocab_size, false);
+        let vocab = Vocab::build(&[], &alphabet, &merges);
+        (word_counts, Tokenizer::new(false, Normalizer::default(), re, &merges, vocab, &[], None))
+    }
+
+    // Training semantics: apply each merge in order across the whole word.
+    fn replay(word: &str, ranks: &Ranks) -> Vec<String> {
+        let mut merges: Vec<(&TokenPair, &usize)> = ranks.iter().collect();
+        merges.sort_by_key(|(_, &rank)| rank);
+        let mut tokens = split_chars(word);
+        for ((a, b), _) in merges {
+            let mut merged = Vec::new();
+            let mut i = 0;
+            while i < tokens.len() {
+                if i + 1 < tokens.len() && &tokens[i] == a && &tokens[i + 1] == b {
+                    merged.push(format!("{a}{b}"));
+                    i += 2;
+                } else {
+                    merged.push(tokens[i].clone());
+                    i += 1;
+                }
+            }
+            tokens = merged;
+        }
+        tokens
+    }
+
+    #[test]
+    fn lowest_rank_pair_merges_first() {
+        let merges = vec![("b".to_string(), "c".to_string()), ("a".to_string(), "b".to_string())];
+        let ranks: Ranks = merges.into_iter().enumerate().map(|(rank, pair)| (pair, rank)).collect();
+        // Greedy left-to-right would produce ["ab", "c"]
+        assert_eq!(apply_merges_to_tokens(split_chars("abc"), &ranks), ["a", "bc"]);
+    }
+
+    #[test]
+    fn training_words_reencode_to_trained_tokens() {
+        for vocab_size in [20, 30, 45, 80] {
+            let (word_counts, tokenizer) = tokenizer_for(CORPUS, vocab_size);
+            for (word, _) in word_counts.iter() {
+                assert_eq!(tokenizer.encode_word(split_chars(word)), replay(word, &tokenizer.ranks), "word {word:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn encode_maps_every_token_to_an_id() {
+        let (_, tokenizer) = tokenizer_for(CORPUS, 60);
+        let ids = tokenizer.encode("the cat sat").unwrap();
+        let tokens: Vec<&str> = ids.iter().map(|&id| tokenizer.vocab.id_to_token(id).unwrap()).collect();
+        assert_eq!(tokens.concat(), "the cat sat");
+    }
+
+    #[test]
+    fn decode_round_trips_char_and_byte_level() {
+        let (_, tokenizer) = tokenizer_for(CORPUS, 60);
+        let ids = tokenizer.encode("the dog ran").unwrap();
+        assert_eq!(tokenizer.decode(&ids, false).unwrap(), "the dog ran");
+
+        let re = Pretokenizer::default();
+        let mut word_counts = WordCounts::new();
+        word_counts.add_bytes(&re, CORPUS.as_bytes()).unwrap();
+        let (alphabet, merges) = learn_merges(&word_counts, 300, true);
+        let specials = ["<|endoftext|>".to_string()];
+        let vocab = Vocab::build(&specials, &alphabet, &merges);
+        let tokenizer = Tokenizer::new(true, Normalizer::default(), re, &merges, vocab, &specials, None);
+
+        let text = "the 猫 sat \u{1F600}";
+        let mut ids = tokenizer.encode(text).unwrap();
+        assert_eq!(tokenizer.decode(&ids, false).unwrap(), text);
+        ids.push(0);
+        assert_eq!(tokenizer.decode(&ids, false).unwrap(), format!("{text}<|endoftext|>"));
+        assert_eq!(tokenizer.decode(&ids, true).unwrap(), text);
+
+        let invalid = tokenizer.encode_bytes(b"ok \xff").unwrap();
+        assert!(tokenizer.decode(&invalid, false).is_err());
+        assert_eq!(tokenizer.decode_lossy(&invalid, false), "ok \u{FFFD}");
+    }
+
+    #[test]
+    fn special_tokens_are_matched_verbatim() {
+        let (word_counts, tokenizer) = tokenizer_for(CORPUS, 60);
+        let (alphabet, merges) = learn_merges(&word_counts, 60, false);
+        let specials = ["<|eos|>".to_string(), "<|unk|>".to_string(), "<|eos|><|eos|>".to_string()];
+        let vocab = Vocab::build(&specials, &alphabet, &merges);
+        let tokenizer = Tokenizer::new(false, Normalizer::default(), tokenizer.pretokenizer, &merges, vocab, &specials, Some("<|unk|>"));
+
+        let ids = tokenizer.encode("the cat<|eos|><|eos|>the <|eos|>q").unwrap();
+        assert_eq!(&ids[ids.len() - 2..], &[0, 1]);
+        assert_eq!(ids.iter().filter(|&&id| id == 2).count(), 1);
+        assert_eq!(tokenizer.decode(&ids, false).unwrap(), "the cat<|eos|><|eos|>the <|eos|><|unk|>");
+        assert_eq!(tokenizer.decode(&ids, true).unwrap(), "the catthe ");
+    }
+
+    #[test]
+    fn char_mode_encode_bytes_encodes_text() {
+        let (_, tokenizer) = tokenizer_for("naïve café", 40);
+        let text = "café naïve";
+        assert_eq!(tokenizer.encode_bytes(text.as_bytes()).unwrap(), tokenizer.encode(text).unwrap());
+        assert!(!tokenizer.encode_bytes("é".as_bytes()).unwrap().is_empty());
+        assert!(matches!(tokenizer.encode_bytes(b"caf\xe9"), Err(TokenthingError::Utf8 { offset: 3, .. })));
+    }
+}
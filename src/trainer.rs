@@ -0,0 +1,540 @@
+use std::{collections::{BTreeSet, BinaryHeap, HashMap, HashSet}, path::{Path, PathBuf}, thread};
+
+use crate::{
+    byte_level::{byte_to_unicode, split_chars},
+    corpus::CHUNK_SIZE,
+    special::SpecialSplitter,
+    mix::{check_shares, mix_factors},
+    Config, Corpus, CorpusReport, CorpusStats, InvalidUtf8, Normalizer, Pretokenizer, RecordSeparator, Result, SpecialTokens, TokenPair, TokenizerModel, TokenthingError, TrainingMetadata,
+    Vocab, WordCounts, MODEL_VERSION,
+};
+
+// Pair of interned symbol ids, as used inside the trainer
+type SymbolPair = (u32, u32);
+// Wide enough that no sum of u64 word counts overflows
+type PairCounts = HashMap<SymbolPair, i128>;
+
+/// Settings for a training run. Build one with [`Trainer::builder`] or
+/// [`Trainer::from_config`].
+#[derive(Debug, Clone)]
+pub struct Trainer {
+    vocab_size: usize,
+    pub(crate) byte_level: bool,
+    pub(crate) num_threads: usize,
+    pub(crate) normalizer: Normalizer,
+    pub(crate) pretokenizer: Pretokenizer,
+    pub(crate) invalid_utf8: InvalidUtf8,
+    record_separator: Option<RecordSeparator>,
+    pub(crate) text_field: Option<String>,
+    pub(crate) keep_newlines: bool,
+    // Bytes per batch handed to a counting thread
+    pub(crate) chunk_size: usize,
+    hf_cache_dir: Option<PathBuf>,
+    special_tokens: SpecialTokens,
+    pub(crate) specials: SpecialSplitter,
+}
+
+/// Builder for [`Trainer`]. Defaults: 30000 tokens, char mode, all available
+/// cores, documents cut as the file extension suggests (one per line for
+/// plain text) with line endings dropped, no normalization,
+/// the default pretokenizer pattern, no special tokens, and failing on invalid
+/// UTF-8 (keeping the raw bytes in byte-level mode).
+#[derive(Debug, Clone)]
+pub struct TrainerBuilder {
+    trainer: Trainer,
+    invalid_utf8: Option<InvalidUtf8>,
+}
+
+impl TrainerBuilder {
+    /// Target vocabulary size: special tokens, base alphabet and merges. In
+    /// byte-level mode it must fit the 256 byte symbols and the special tokens.
+    pub fn vocab_size(mut self, vocab_size: usize) -> Self {
+        self.trainer.vocab_size = vocab_size;
+        self
+    }
+
+    /// Train over raw bytes (GPT-2 style) instead of chars.
+    pub fn byte_level(mut self, byte_level: bool) -> Self {
+        self.trainer.byte_level = byte_level;
+        self
+    }
+
+    /// Threads used to count the corpus.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.trainer.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// How the corpus is cut into documents, instead of going by the file
+    /// extension (see [`RecordSeparator::for_path`]).
+    pub fn record_separator(mut self, record_separator: RecordSeparator) -> Self {
+        self.trainer.record_separator = Some(record_separator);
+        self
+    }
+
+    /// Field (JSONL) or column (CSV, Arrow, Parquet) holding the text. Without one, `text`,
+    /// then `content`, then the first string field is used.
+    pub fn text_field(mut self, text_field: impl Into<String>) -> Self {
+        self.trainer.text_field = Some(text_field.into());
+        self
+    }
+
+    /// Keep line endings inside documents as text, so newline tokens are
+    /// learned, instead of counting each line separately.
+    pub fn keep_newlines(mut self, keep_newlines: bool) -> Self {
+        self.trainer.keep_newlines = keep_newlines;
+        self
+    }
+
+    /// Hugging Face cache root that corpora with an `hf_dataset` are looked up
+    /// in, instead of `$HF_HOME` (see [`hf_dataset_files`](crate::hf_dataset_files)).
+    pub fn hf_cache_dir(mut self, hf_cache_dir: impl Into<PathBuf>) -> Self {
+        self.trainer.hf_cache_dir = Some(hf_cache_dir.into());
+        self
+    }
+
+    /// Applied to every line before pretokenization.
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.trainer.normalizer = normalizer;
+        self
+    }
+
+    /// How text is split into pretokens before merging.
+    pub fn pretokenizer(mut self, pretokenizer: Pretokenizer) -> Self {
+        self.trainer.pretokenizer = pretokenizer;
+        self
+    }
+
+    /// Policy for corpus lines that are not valid UTF-8.
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+        self.invalid_utf8 = Some(invalid_utf8);
+        self
+    }
+
+    /// Reserved tokens, given the first IDs and left out of training.
+    pub fn special_tokens(mut self, special_tokens: SpecialTokens) -> Self {
+        self.trainer.special_tokens = special_tokens;
+        self
+    }
+
+    /// Fails if the settings contradict each other.
+    pub fn build(self) -> Result<Trainer> {
+        let mut trainer = self.trainer;
+        trainer.special_tokens.validate()?;
+        if trainer.record_separator == Some(RecordSeparator::Delimiter(String::new())) {
+            let message = "record_delimiter must not be empty".to_string();
+            return Err(TokenthingError::Config { path: None, message });
+        }
+        let special_count = trainer.special_tokens.tokens().len();
+        if trainer.byte_level && trainer.vocab_size < 256 + special_count {
+            let message = format!(
+                "tokenizer_vocab_size {} is below the 256 byte symbols plus {special_count} special tokens",
+                trainer.vocab_size
+            );
+            return Err(TokenthingError::Config { path: None, message });
+        }
+        trainer.specials = SpecialSplitter::new(&trainer.special_tokens.tokens());
+        trainer.invalid_utf8 = match self.invalid_utf8 {
+            Some(InvalidUtf8::Bytes) if !trainer.byte_level => {
+                let message = "invalid_utf8: bytes needs byte_level: true".to_string();
+                return Err(TokenthingError::Config { path: None, message });
+            }
+            Some(policy) => policy,
+            None if trainer.byte_level => InvalidUtf8::Bytes,
+            None => InvalidUtf8::Fail,
+        };
+        Ok(trainer)
+    }
+}
+
+impl Trainer {
+    pub fn builder() -> TrainerBuilder {
+        TrainerBuilder {
+            trainer: Trainer {
+                vocab_size: 30000,
+                byte_level: false,
+                num_threads: thread::available_parallelism().map_or(1, |n| n.get()),
+                normalizer: Normalizer::default(),
+                pretokenizer: Pretokenizer::default(),
+                invalid_utf8: InvalidUtf8::Fail,
+                record_separator: None,
+                text_field: None,
+                keep_newlines: false,
+                chunk_size: CHUNK_SIZE,
+                hf_cache_dir: None,
+                special_tokens: SpecialTokens::default(),
+                specials: SpecialSplitter::default(),
+            },
+            invalid_utf8: None,
+        }
+    }
+
+    /// A trainer with the settings from `config`.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut builder = Trainer::builder()
+            .vocab_size(config.tokenizer_vocab_size)
+            .byte_level(config.byte_level)
+            .keep_newlines(config.keep_newlines)
+            .normalizer(config.normalizer.clone())
+            .pretokenizer(config.pretokenizer()?)
+            .special_tokens(config.special_tokens.clone());
+        if let Some(record_separator) = config.record_separator()? {
+            builder = builder.record_separator(record_separator);
+        }
+        if let Some(text_field) = &config.text_field {
+            builder = builder.text_field(text_field);
+        }
+        if let Some(hf_cache_dir) = &config.hf_cache_dir {
+            builder = builder.hf_cache_dir(hf_cache_dir);
+        }
+        if let Some(num_threads) = config.num_threads {
+            builder = builder.num_threads(num_threads);
+        }
+        if let Some(invalid_utf8) = config.invalid_utf8 {
+            builder = builder.invalid_utf8(invalid_utf8);
+        }
+        builder.build()
+    }
+
+    /// Count the pretokens of a corpus file.
+    pub fn count_file(&self, file_path: impl AsRef<Path>) -> Result<(WordCounts, CorpusStats)> {
+        WordCounts::from_file(file_path, self)
+    }
+
+    /// The configured record separator, else the one implied by the extension.
+    /// Arrow and Parquet files are binary, so their extension always wins.
+    pub fn record_separator_for(&self, path: &Path) -> RecordSeparator {
+        match (RecordSeparator::for_path(path), &self.record_separator) {
+            (columnar @ (RecordSeparator::Arrow | RecordSeparator::Parquet), _) => columnar,
+            (_, Some(record_separator)) => record_separator.clone(),
+            (implied, None) => implied,
+        }
+    }
+
+    pub fn invalid_utf8(&self) -> InvalidUtf8 {
+        self.invalid_utf8
+    }
+
+    /// Count the pretokens of several corpus files together. Returns the
+    /// stats of each file, in input order.
+    pub fn count_files(&self, file_paths: &[impl AsRef<Path>]) -> Result<(WordCounts, Vec<CorpusStats>)> {
+        WordCounts::from_files(file_paths, self)
+    }
+
+    /// Count several corpora and mix them: each corpus's counts are scaled by
+    /// its weight, or to its share of the mixed counts (see [`Corpus`]).
+    pub fn count_corpora(&self, corpora: &[Corpus]) -> Result<(WordCounts, Vec<CorpusReport>)> {
+        check_shares(corpora)?;
+        let mut counted = Vec::with_capacity(corpora.len());
+        for corpus in corpora {
+            let files = corpus.files(self.hf_cache_dir.as_deref())?;
+            let (word_counts, stats) = self.count_files(&files)?;
+            counted.push((word_counts, files.into_iter().zip(stats).collect()));
+        }
+        let totals: Vec<u64> = counted.iter().map(|(word_counts, _)| word_counts.total()).collect();
+        let factors = mix_factors(corpora, &totals)?;
+
+        let mut mixed = WordCounts::new();
+        let mut reports = Vec::with_capacity(corpora.len());
+        for (((mut word_counts, files), pretokens), scale) in counted.into_iter().zip(totals).zip(factors) {
+            word_counts.scale(scale);
+            mixed.merge(word_counts);
+            reports.push(CorpusReport { files, pretokens, scale });
+        }
+        Ok((mixed, reports))
+    }
+
+    /// Count a corpus file and train on it.
+    pub fn train_file(&self, file_path: impl AsRef<Path>) -> Result<TokenizerModel> {
+        self.train_files(&[file_path])
+    }
+
+    /// Count several corpus files and train on them together.
+    pub fn train_files(&self, file_paths: &[impl AsRef<Path>]) -> Result<TokenizerModel> {
+        let (word_counts, _) = self.count_files(file_paths)?;
+        let mut model = self.train(&word_counts);
+        let names: Vec<String> = file_paths.iter().map(|p| p.as_ref().display().to_string()).collect();
+        model.metadata.corpus = names.join(", ");
+        Ok(model)
+    }
+
+    /// Learn merges from a word table. The returned model's `metadata.corpus`
+    /// is left empty for the caller to fill in.
+    pub fn train(&self, word_counts: &WordCounts) -> TokenizerModel {
+        let (vocab_size, byte_level) = (self.vocab_size, self.byte_level);
+        let pattern = self.pretokenizer.pattern().to_string();
+        let special_tokens = self.special_tokens.tokens();
+        let (alphabet, merges) = learn_merges(word_counts, vocab_size.saturating_sub(special_tokens.len()), byte_level);
+
+        // Same settings and corpus always give the same merges, hence the same fingerprint
+        let mut settings = vec![format!("vocab_size={vocab_size}"), format!("byte_level={byte_level}"), pattern.clone()];
+        if !self.normalizer.is_empty() { settings.push(format!("normalizer={}", self.normalizer)); }
+        if !special_tokens.is_empty() { settings.push(format!("special_tokens={special_tokens:?}")); }
+        let merge_parts = merges.iter().flat_map(|(a, b)| [a.as_str(), b.as_str()]);
+        let fp = fingerprint(settings.iter().map(String::as_str).chain(merge_parts));
+
+        let vocab = Vocab::build(&special_tokens, &alphabet, &merges);
+
+        TokenizerModel {
+            version: MODEL_VERSION,
+            byte_level,
+            normalizer: self.normalizer.clone(),
+            pattern,
+            special_tokens,
+            unk_token: self.special_tokens.unk.clone(),
+            merges,
+            vocab,
+            metadata: TrainingMetadata {
+                corpus: String::new(),
+                vocab_size,
+                alphabet_size: alphabet.len(),
+                words: word_counts.len(),
+                pretokens: word_counts.total(),
+                fingerprint: format!("{fp:016x}"),
+            },
+        }
+    }
+}
+
+// A distinct pretoken as a sequence of interned symbols, with its corpus frequency.
+struct Word {
+    symbols: Vec<u32>,
+    count: u64,
+}
+
+impl Word {
+    // Merge every occurrence of `pair` into `new_id`, left to right.
+    // Returns the pair-count changes for one occurrence of this word.
+    fn merge(&mut self, pair: SymbolPair, new_id: u32) -> Vec<(SymbolPair, i64)> {
+        if !self.symbols.windows(2).any(|w| (w[0], w[1]) == pair) {
+            return Vec::new();
+        }
+        let mut changes: Vec<(SymbolPair, i64)> = self.symbols.windows(2).map(|w| ((w[0], w[1]), -1)).collect();
+        let mut merged = Vec::with_capacity(self.symbols.len());
+        let mut i = 0;
+        while i < self.symbols.len() {
+            if i + 1 < self.symbols.len() && (self.symbols[i], self.symbols[i + 1]) == pair {
+                merged.push(new_id);
+                i += 2;
+            } else {
+                merged.push(self.symbols[i]);
+                i += 1;
+            }
+        }
+        self.symbols = merged;
+        changes.extend(self.symbols.windows(2).map(|w| ((w[0], w[1]), 1)));
+        changes
+    }
+}
+
+// Priority-queue entry. Counts only ever drop for existing pairs, so stale
+// entries are detected on pop and re-queued with their current count.
+#[derive(PartialEq, Eq)]
+struct Candidate {
+    count: i128,
+    pair: SymbolPair,
+    // Symbol strings of the pair, for tie-breaking
+    key: TokenPair,
+}
+
+impl Candidate {
+    fn new(count: i128, pair: SymbolPair, symbols: &[String]) -> Self {
+        let key = (symbols[pair.0 as usize].clone(), symbols[pair.1 as usize].clone());
+        Candidate { count, pair, key }
+    }
+}
+
+// Highest count first; ties go to the lexicographically smallest pair so
+// training never depends on hash iteration order.
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.count.cmp(&other.count).then_with(|| other.key.cmp(&self.key))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// FNV-1a over a sequence of byte strings, each followed by a 0xff separator
+// (never valid UTF-8). Stable across runs and platforms, unlike std's hasher.
+fn fingerprint<I, T>(parts: I) -> u64
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for &b in part.as_ref().iter().chain(&[0xff]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// Learn BPE merges from a word table, until alphabet + merges reach vocab_size.
+// Returns the base alphabet in ID order and the merges in rank order.
+pub(crate) fn learn_merges(word_counts: &WordCounts, vocab_size: usize, byte_level: bool) -> (Vec<String>, Vec<TokenPair>) {
+    let mut merges: Vec<TokenPair> = Vec::new();
+
+    // Base symbols: every byte in byte-level mode, else the chars seen in the corpus
+    // (in byte order, else sorted)
+    let alphabet: Vec<String> = if byte_level {
+        byte_to_unicode().iter().map(char::to_string).collect()
+    } else {
+        let chars: BTreeSet<String> = word_counts.iter().flat_map(|(w, _)| split_chars(w)).collect();
+        chars.into_iter().collect()
+    };
+
+    // Intern symbols so the merge loop works on integer ids
+    let mut symbols: Vec<String> = alphabet.to_vec();
+    let mut symbol_ids: HashMap<String, u32> = symbols.iter().enumerate().map(|(i, s)| (s.clone(), i as u32)).collect();
+    // Sorted so word indices (and everything derived from them) are reproducible
+    let mut sorted_words: Vec<(&str, u64)> = word_counts.iter().collect();
+    sorted_words.sort_unstable();
+    let mut words: Vec<Word> = sorted_words
+        .into_iter()
+        .map(|(w, count)| Word { symbols: w.chars().map(|c| symbol_ids[c.to_string().as_str()]).collect(), count })
+        .collect();
+
+    // Initial pair counts, and which words each pair occurs in
+    let mut pair_counts = PairCounts::new();
+    let mut pair_words: HashMap<SymbolPair, HashSet<usize>> = HashMap::new();
+    for (idx, word) in words.iter().enumerate() {
+        for w in word.symbols.windows(2) {
+            *pair_counts.entry((w[0], w[1])).or_insert(0) += i128::from(word.count);
+            pair_words.entry((w[0], w[1])).or_default().insert(idx);
+        }
+    }
+    let mut queue: BinaryHeap<Candidate> = pair_counts.iter().map(|(&pair, &count)| Candidate::new(count, pair, &symbols)).collect();
+
+    // Merge the most frequent pair until alphabet + merges reach vocab_size
+    while alphabet.len() + merges.len() < vocab_size {
+        let Some(top) = queue.pop() else { break; };
+        let count = pair_counts.get(&top.pair).copied().unwrap_or(0);
+        if count != top.count {
+            if count > 0 { queue.push(Candidate { count, ..top }); }
+            continue;
+        }
+
+        let (a, b) = top.pair;
+        let merged = format!("{}{}", symbols[a as usize], symbols[b as usize]);
+        let new_id = *symbol_ids.entry(merged.clone()).or_insert_with(|| {
+            symbols.push(merged.clone());
+            (symbols.len() - 1) as u32
+        });
+        merges.push((symbols[a as usize].clone(), symbols[b as usize].clone()));
+
+        // Update only the words containing the pair
+        let mut touched: HashSet<SymbolPair> = HashSet::new();
+        for idx in pair_words.remove(&top.pair).unwrap_or_default() {
+            let word = &mut words[idx];
+            for (pair, delta) in word.merge(top.pair, new_id) {
+                *pair_counts.entry(pair).or_insert(0) += i128::from(delta) * i128::from(word.count);
+                if delta > 0 { pair_words.entry(pair).or_default().insert(idx); }
+                touched.insert(pair);
+            }
+        }
+        for pair in touched {
+            match pair_counts.get(&pair) {
+                Some(&count) if count > 0 => {
+                    // Only pairs with the new symbol can have grown
+                    if pair.0 == new_id || pair.1 == new_id { queue.push(Candidate::new(count, pair, &symbols)); }
+                }
+                _ => { pair_counts.remove(&pair); }
+            }
+        }
+    }
+
+    (alphabet, merges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    // BPE by recounting every pair from scratch before each merge
+    fn naive_merges(word_counts: &WordCounts, num_merges: usize) -> Vec<TokenPair> {
+        let mut words: Vec<(Vec<String>, u64)> = word_counts.iter().map(|(w, count)| (split_chars(w), count)).collect();
+        let mut merges = Vec::new();
+        while merges.len() < num_merges {
+            let mut pair_counts: HashMap<TokenPair, u64> = HashMap::new();
+            for (symbols, count) in &words {
+                for w in symbols.windows(2) { *pair_counts.entry((w[0].clone(), w[1].clone())).or_insert(0) += count; }
+            }
+            // Highest count, then smallest pair
+            let Some((pair, _)) = pair_counts.into_iter().max_by(|(a, x), (b, y)| x.cmp(y).then_with(|| b.cmp(a))) else { break; };
+            for (symbols, _) in &mut words {
+                let mut merged = Vec::with_capacity(symbols.len());
+                let mut i = 0;
+                while i < symbols.len() {
+                    if i + 1 < symbols.len() && (&symbols[i], &symbols[i + 1]) == (&pair.0, &pair.1) {
+                        merged.push(format!("{}{}", pair.0, pair.1));
+                        i += 2;
+                    } else {
+                        merged.push(symbols[i].clone());
+                        i += 1;
+                    }
+                }
+                *symbols = merged;
+            }
+            merges.push(pair);
+        }
+        merges
+    }
+
+    #[test]
+    fn incremental_merges_match_recounting() {
+        // xorshift, so the word tables are the same on every run
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        for _ in 0..50 {
+            let mut word_counts = WordCounts::new();
+            for _ in 0..1 + next(30) {
+                // A small alphabet gives repeated symbols, overlapping pairs and ties
+                let word: String = (0..1 + next(8)).map(|_| char::from(b'a' + next(4) as u8)).collect();
+                word_counts.add(&word, 1 + next(5));
+            }
+            let (alphabet, merges) = learn_merges(&word_counts, 1000, false);
+            assert_eq!(merges, naive_merges(&word_counts, 1000 - alphabet.len()));
+        }
+    }
+
+    #[test]
+    fn retraining_is_reproducible() {
+        let mut corpus = String::new();
+        for i in 0..500 { corpus.push_str(&format!("sentence {i} about tokens, merges and word{}\n", i % 13)); }
+        let path = env::temp_dir().join(format!("tokenthing-retrain-{}.txt", process::id()));
+        fs::write(&path, corpus).unwrap();
+        let train = |num_threads: usize| {
+            let trainer = Trainer::builder().vocab_size(120).num_threads(num_threads).build().unwrap();
+            trainer.train_file(&path).unwrap()
+        };
+        let first = train(1);
+        for num_threads in [1, 4] {
+            let model = train(num_threads);
+            assert_eq!(model.merges, first.merges, "{num_threads} threads");
+            assert_eq!(model.metadata.fingerprint, first.metadata.fingerprint, "{num_threads} threads");
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(!first.merges.is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_the_smallest_pair() {
+        let mut word_counts = WordCounts::new();
+        for word in ["zy", "mn", "ab", "ba"] { word_counts.add(word, 3); }
+        let (_, merges) = learn_merges(&word_counts, 100, false);
+        let pairs = [("a", "b"), ("b", "a"), ("m", "n"), ("z", "y")].map(|(a, b)| (a.to_string(), b.to_string()));
+        assert_eq!(merges, pairs);
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::TokenPair;
+
+/// Bidirectional token <-> ID map. IDs are dense and assigned in a fixed order:
+///   1. special tokens, in declaration order
+///   2. base alphabet: byte order in byte-level mode, else sorted by char
+///   3. merged tokens, in merge rank order
+///
+/// A token that is already present keeps its first ID. Saved models store the
+/// map itself, so IDs never shuffle once a model exists.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(into = "BTreeMap<String, u32>", try_from = "BTreeMap<String, u32>")]
+pub struct Vocab {
+    id_to_token: Vec<String>,
+    token_to_id: HashMap<String, u32>,
+}
+
+impl Vocab {
+    pub(crate) fn build(special_tokens: &[String], alphabet: &[String], merges: &[TokenPair]) -> Self {
+        let mut vocab = Vocab::default();
+        for token in special_tokens.iter().chain(alphabet) { vocab.insert(token); }
+        for (a, b) in merges { vocab.insert(&format!("{a}{b}")); }
+        vocab
+    }
+
+    // Add a token if missing; returns its ID either way.
+    fn insert(&mut self, token: &str) -> u32 {
+        if let Some(id) = self.token_to_id(token) { return id; }
+        let id = self.id_to_token.len() as u32;
+        self.id_to_token.push(token.to_string());
+        self.token_to_id.insert(token.to_string(), id);
+        id
+    }
+
+    pub fn token_to_id(&self, token: &str) -> Option<u32> {
+        self.token_to_id.get(token).copied()
+    }
+
+    pub fn id_to_token(&self, id: u32) -> Option<&str> {
+        self.id_to_token.get(id as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.id_to_token.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.id_to_token.is_empty()
+    }
+}
+
+impl From<Vocab> for BTreeMap<String, u32> {
+    fn from(vocab: Vocab) -> Self {
+        vocab.token_to_id.into_iter().collect()
+    }
+}
+
+// Accept only dense, unique IDs so lookups in both directions agree.
+impl TryFrom<BTreeMap<String, u32>> for Vocab {
+    type Error = String;
+
+    fn try_from(map: BTreeMap<String, u32>) -> Result<Self, Self::Error> {
+        let mut id_to_token: Vec<Option<String>> = vec![None; map.len()];
+        for (token, &id) in &map {
+            match id_to_token.get_mut(id as usize) {
+                Some(slot @ None) => *slot = Some(token.clone()),
+                Some(Some(other)) => return Err(format!("tokens {other:?} and {token:?} share ID {id}")),
+                None => return Err(format!("token {token:?} has ID {id}, outside 0..{}", map.len())),
+            }
+        }
+        Ok(Vocab {
+            id_to_token: id_to_token.into_iter().flatten().collect(),
+            token_to_id: map.into_iter().collect(),
+        })
+    }
+}
@@ -0,0 +1,387 @@
+use std::{borrow::Cow, collections::HashMap, fmt, ops::AddAssign, path::Path, str::FromStr, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex}, thread};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    byte_level::bytes_to_symbols,
+    corpus::{jsonl_text, lines, read_batches, Batch, RecordSeparator},
+    Pretokenizer, Result, TokenthingError, Trainer,
+};
+
+/// What to do with corpus documents that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidUtf8 {
+    /// Stop with an error naming the file and line
+    Fail,
+    /// Leave the document out
+    Skip,
+    /// Replace bad sequences with U+FFFD
+    Replace,
+    /// Train on the raw bytes (byte-level mode only)
+    Bytes,
+}
+
+impl FromStr for InvalidUtf8 {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(InvalidUtf8::Fail),
+            "skip" => Ok(InvalidUtf8::Skip),
+            "replace" => Ok(InvalidUtf8::Replace),
+            "bytes" => Ok(InvalidUtf8::Bytes),
+            _ => Err(format!("unknown invalid-UTF-8 policy {s:?} (expected fail, skip, replace or bytes)")),
+        }
+    }
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InvalidUtf8::Fail => "fail",
+            InvalidUtf8::Skip => "skip",
+            InvalidUtf8::Replace => "replace",
+            InvalidUtf8::Bytes => "bytes",
+        })
+    }
+}
+
+/// What reading a corpus saw, for the end-of-run summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CorpusStats {
+    pub lines: u64,
+    /// Bytes read, after decompression
+    pub bytes: u64,
+    /// Documents, as cut by the `RecordSeparator`
+    pub documents: u64,
+    /// Documents that were not valid UTF-8 and were handled by the `InvalidUtf8` policy
+    pub invalid_utf8_documents: u64,
+}
+
+impl AddAssign for CorpusStats {
+    fn add_assign(&mut self, other: Self) {
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+        self.documents += other.documents;
+        self.invalid_utf8_documents += other.invalid_utf8_documents;
+    }
+}
+
+/// Deduplicated pretoken frequencies: the trainer's input. In byte-level mode
+/// words are keyed by their byte symbols, so every char of a key is one base symbol.
+#[derive(Debug, Clone, Default)]
+pub struct WordCounts {
+    counts: HashMap<String, u64>,
+}
+
+impl WordCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a corpus file once and count each distinct pretoken; see
+    /// [`WordCounts::from_files`].
+    pub fn from_file(file_path: impl AsRef<Path>, trainer: &Trainer) -> Result<(Self, CorpusStats)> {
+        let (word_counts, stats) = Self::from_files(&[file_path], trainer)?;
+        Ok((word_counts, stats[0]))
+    }
+
+    /// Read the corpus files once, in order, and count each distinct pretoken
+    /// with the trainer's record separator, special tokens, normalizer,
+    /// pretokenizer and invalid-UTF-8 policy. The files are cut into
+    /// record-aligned batches, each batch is counted on one of the trainer's
+    /// threads (map) and the partial tables are summed (reduce). Returns the
+    /// stats of each file, in input order.
+    pub fn from_files(file_paths: &[impl AsRef<Path>], trainer: &Trainer) -> Result<(Self, Vec<CorpusStats>)> {
+        let paths: Vec<&Path> = file_paths.iter().map(AsRef::as_ref).collect();
+        let separators: Vec<RecordSeparator> = paths.iter().map(|path| trainer.record_separator_for(path)).collect();
+        let num_threads = trainer.num_threads;
+        // Batches are tagged with the index of their file
+        let (sender, receiver) = mpsc::sync_channel::<(usize, Batch)>(num_threads * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+        // Set by a failing worker so reading and the other workers stop early
+        let cancelled = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..num_threads.max(1))
+                .map(|_| {
+                    let receiver = Arc::clone(&receiver);
+                    let (paths, separators, cancelled) = (&paths, &separators, &cancelled);
+                    scope.spawn(move || {
+                        let mut word_counts = WordCounts::new();
+                        let mut stats = vec![CorpusStats::default(); paths.len()];
+                        let mut count = |file: usize, batch: Batch| {
+                            let (path, separator, stats) = (paths[file], &separators[file], &mut stats[file]);
+                            match batch {
+                                Batch::Chunk { first_line, bytes } => {
+                                    for (i, record) in separator.split(&bytes) {
+                                        word_counts.add_record(trainer, separator, record, path, first_line + i, stats)?;
+                                    }
+                                }
+                                Batch::Documents(documents) => {
+                                    for (line, document) in documents {
+                                        word_counts.add_record(trainer, separator, &document, path, line, stats)?;
+                                    }
+                                }
+                            }
+                            Ok::<_, TokenthingError>(())
+                        };
+                        while !cancelled.load(Ordering::Relaxed) {
+                            let Ok((file, batch)) = receiver.lock().unwrap().recv() else { break; };
+                            if let Err(e) = count(file, batch) {
+                                cancelled.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                        Ok((word_counts, stats))
+                    })
+                })
+                .collect();
+            // Once every worker has exited, sends fail instead of blocking
+            drop(receiver);
+
+            let mut stats = vec![CorpusStats::default(); paths.len()];
+            let mut read_result = Ok(());
+            for (file, path) in paths.iter().enumerate() {
+                let mut sent = true;
+                read_result = read_batches(
+                    path,
+                    &separators[file],
+                    trainer.text_field.as_deref(),
+                    trainer.chunk_size,
+                    &mut stats[file],
+                    |batch| {
+                        sent = !cancelled.load(Ordering::Relaxed) && sender.send((file, batch)).is_ok();
+                        sent
+                    },
+                );
+                // Workers only exit early on error, which join reports below
+                if read_result.is_err() || !sent { break; }
+            }
+            drop(sender);
+
+            let mut word_counts = WordCounts::new();
+            for worker in workers {
+                let (counts, worker_stats) = worker.join().unwrap()?;
+                word_counts.merge(counts);
+                for (total, partial) in stats.iter_mut().zip(worker_stats) { *total += partial; }
+            }
+            read_result?;
+            Ok((word_counts, stats))
+        })
+    }
+
+    // Map step for one record: handle invalid UTF-8 per the trainer's policy,
+    // pull the text out of JSONL, then normalize and count it. `line` is the
+    // 1-based line the record starts on.
+    fn add_record(
+        &mut self,
+        trainer: &Trainer,
+        separator: &RecordSeparator,
+        record: &[u8],
+        path: &Path,
+        line: u64,
+        stats: &mut CorpusStats,
+    ) -> Result<()> {
+        stats.documents += 1;
+        let record: Cow<[u8]> = match std::str::from_utf8(record) {
+            Ok(_) => Cow::Borrowed(record),
+            Err(e) => {
+                stats.invalid_utf8_documents += 1;
+                match trainer.invalid_utf8 {
+                    InvalidUtf8::Fail => {
+                        // Point at the line holding the bad byte, not the document start
+                        let before = &record[..e.valid_up_to()];
+                        let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+                        let line = line + before.iter().filter(|&&b| b == b'\n').count() as u64;
+                        let offset = e.valid_up_to() - line_start;
+                        return Err(TokenthingError::Utf8 { path: Some(path.to_path_buf()), line: Some(line), offset });
+                    }
+                    InvalidUtf8::Skip => return Ok(()),
+                    InvalidUtf8::Replace => Cow::Owned(String::from_utf8_lossy(record).into_owned().into_bytes()),
+                    // Only reachable in byte-level mode; the trainer rejects it otherwise
+                    InvalidUtf8::Bytes => Cow::Borrowed(record),
+                }
+            }
+        };
+        let document: Cow<[u8]> = if *separator == RecordSeparator::Jsonl {
+            // JSON is UTF-8 by definition, so a record kept as raw bytes is read lossily
+            let text = jsonl_text(String::from_utf8_lossy(&record).trim_end(), trainer.text_field.as_deref())
+                .map_err(|message| TokenthingError::Corpus { path: path.to_path_buf(), line: Some(line), message })?;
+            match text {
+                Some(text) => Cow::Owned(text.into_bytes()),
+                None => {
+                    stats.documents -= 1;
+                    return Ok(());
+                }
+            }
+        } else {
+            record
+        };
+        // The pretokenizer can fail on pathological input; point at the record
+        self.add_document(trainer, &document)
+            .map_err(|e| TokenthingError::Corpus { path: path.to_path_buf(), line: Some(line), message: e.to_string() })
+    }
+
+    // Count one document: as a whole when keeping newlines, else line by line.
+    fn add_document(&mut self, trainer: &Trainer, document: &[u8]) -> Result<()> {
+        if trainer.keep_newlines {
+            self.add_normalized(trainer, document)
+        } else {
+            lines(document).try_for_each(|line| self.add_normalized(trainer, line))
+        }
+    }
+
+    // Count one piece of text, leaving out special tokens. `text` is valid
+    // UTF-8 unless the trainer is byte-level.
+    fn add_normalized(&mut self, trainer: &Trainer, text: &[u8]) -> Result<()> {
+        for (piece, special) in trainer.specials.split_bytes(text) {
+            if special { continue; }
+            let piece = trainer.normalizer.normalize_bytes(piece);
+            if trainer.byte_level {
+                self.add_bytes(&trainer.pretokenizer, &piece)?;
+            } else {
+                self.add_text(&trainer.pretokenizer, std::str::from_utf8(&piece).expect("normalized valid UTF-8"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reduce step: fold another table into this one.
+    pub fn merge(&mut self, other: WordCounts) {
+        if self.counts.len() < other.counts.len() {
+            let mine = std::mem::replace(&mut self.counts, other.counts);
+            for (w, c) in mine { self.add_owned(w, c); }
+        } else {
+            for (w, c) in other.counts { self.add_owned(w, c); }
+        }
+    }
+
+    /// Add `count` occurrences of `word`. Counts saturate at `u64::MAX`.
+    pub fn add(&mut self, word: &str, count: u64) {
+        match self.counts.get_mut(word) {
+            Some(c) => *c = c.saturating_add(count),
+            None => { self.counts.insert(word.to_string(), count); }
+        }
+    }
+
+    fn add_owned(&mut self, word: String, count: u64) {
+        let c = self.counts.entry(word).or_insert(0);
+        *c = c.saturating_add(count);
+    }
+
+    /// Collapse the pretokens of `text` into the table. Fails, having counted
+    /// the pretokens before it, if the pretokenizer hits its backtracking limit.
+    pub fn add_text(&mut self, pretokenizer: &Pretokenizer, text: &str) -> Result<()> {
+        for pretoken in pretokenizer.split(text) { self.add(pretoken?, 1); }
+        Ok(())
+    }
+
+    /// Byte-level counterpart of `add_text`.
+    pub fn add_bytes(&mut self, pretokenizer: &Pretokenizer, bytes: &[u8]) -> Result<()> {
+        for pretoken in pretokenizer.split_bytes(bytes) { self.add(&bytes_to_symbols(pretoken?), 1); }
+        Ok(())
+    }
+
+    /// Multiply every count by `factor`, rounding to the nearest integer and
+    /// saturating at `u64::MAX`. Words whose count rounds to 0 are dropped.
+    pub fn scale(&mut self, factor: f64) {
+        if factor == 1.0 { return; }
+        // `as` saturates, and maps NaN to 0
+        for count in self.counts.values_mut() { *count = (*count as f64 * factor).round() as u64; }
+        self.counts.retain(|_, count| *count > 0);
+    }
+
+    /// Number of distinct words.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Number of pretokens counted, duplicates included, saturating at `u64::MAX`.
+    pub fn total(&self) -> u64 {
+        self.counts.values().fold(0, |total, &c| total.saturating_add(c))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> + '_ {
+        self.counts.iter().map(|(w, &c)| (w.as_str(), c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeMap, env, fs, process};
+    use crate::corpus::CHUNK_SIZE;
+
+    fn table(word_counts: &WordCounts) -> BTreeMap<String, u64> {
+        word_counts.iter().map(|(w, c)| (w.to_string(), c)).collect()
+    }
+
+    #[test]
+    fn threaded_counts_match_serial() {
+        let mut corpus = String::new();
+        for i in 0..2000 {
+            corpus.push_str(&format!("line {i} of the naïve café corpus, word{} and more", i % 37));
+            if i % 5 == 0 { corpus.push_str("<|doc|>"); }
+            corpus.push('\n');
+            if i % 7 == 0 { corpus.push('\n'); }
+        }
+        let path = env::temp_dir().join(format!("tokenthing-threads-{}.txt", process::id()));
+        fs::write(&path, corpus).unwrap();
+
+        let separators = [RecordSeparator::Newline, RecordSeparator::BlankLine, RecordSeparator::Delimiter("<|doc|>".to_string())];
+        for separator in separators {
+            let count = |num_threads: usize, chunk_size: usize| {
+                let mut trainer = Trainer::builder().num_threads(num_threads).record_separator(separator.clone()).build().unwrap();
+                // Small chunks force many batches, cut in the middle of records
+                trainer.chunk_size = chunk_size;
+                WordCounts::from_file(&path, &trainer).unwrap()
+            };
+            let (serial, serial_stats) = count(1, CHUNK_SIZE);
+            for (num_threads, chunk_size) in [(1, 64), (4, 64), (4, 1000)] {
+                let (threaded, threaded_stats) = count(num_threads, chunk_size);
+                assert_eq!(table(&threaded), table(&serial), "{separator}, {num_threads} threads, {chunk_size} byte chunks");
+                assert_eq!(threaded_stats, serial_stats, "{separator}, {num_threads} threads, {chunk_size} byte chunks");
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_utf8_policies() {
+        let record: &[u8] = b"one line\nsecond line\nbad caf\xe9 byte\n";
+        let count = |invalid_utf8: InvalidUtf8, record: &[u8]| {
+            let trainer = Trainer::builder().byte_level(true).invalid_utf8(invalid_utf8).build().unwrap();
+            let mut word_counts = WordCounts::new();
+            let mut stats = CorpusStats::default();
+            let path = Path::new("corpus.txt");
+            let result = word_counts.add_record(&trainer, &RecordSeparator::BlankLine, record, path, 10, &mut stats);
+            result.map(|()| (table(&word_counts), stats))
+        };
+        let (valid, _) = count(InvalidUtf8::Fail, b"one line\nsecond line\nbad caf byte\n").unwrap();
+
+        let (skipped, stats) = count(InvalidUtf8::Skip, record).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!((stats.documents, stats.invalid_utf8_documents), (1, 1));
+
+        let (replaced, stats) = count(InvalidUtf8::Replace, record).unwrap();
+        let (lossy, _) = count(InvalidUtf8::Fail, String::from_utf8_lossy(record).as_bytes()).unwrap();
+        assert_eq!(replaced, lossy);
+        assert_eq!((stats.documents, stats.invalid_utf8_documents), (1, 1));
+
+        let (raw, stats) = count(InvalidUtf8::Bytes, record).unwrap();
+        assert!(raw.keys().any(|word| word.contains(&bytes_to_symbols(b"\xe9"))));
+        assert!(valid.keys().all(|word| raw.contains_key(word) || word.contains("caf")));
+        assert_eq!((stats.documents, stats.invalid_utf8_documents), (1, 1));
+
+        // The record starts on line 10; the bad byte is on its third line
+        match count(InvalidUtf8::Fail, record) {
+            Err(TokenthingError::Utf8 { line, offset, .. }) => assert_eq!((line, offset), (Some(12), 7)),
+            other => panic!("expected a UTF-8 error, got {other:?}"),
+        }
+    }
+}